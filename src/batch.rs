@@ -0,0 +1,468 @@
+//! Bending every file in a directory or matched by a glob, from the `inputs` option.
+//!
+//! With `sequence` set, the files are frames of an animation instead: each one knows its index, so that
+//! keyframed options and `drift` can change smoothly from one frame to the next.
+
+use super::configuration::Configuration;
+use super::error::Error;
+
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::Value;
+
+/// A file found through the `inputs` option.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchInput {
+    /// The path of the file.
+    pub path: String,
+    /// The path of the file relative to the directory it was found in, mirrored in `outputdir`.
+    pub relative: PathBuf,
+    /// The index of the file, if it's a frame of a sequence.
+    pub frame: Option<usize>,
+}
+
+impl BatchInput {
+    /// Creates the configuration bending this file, with its output mirrored into `outputdir`.
+    /// Frames of a sequence also set `frame`, and resolve every keyframed option to their value at it.
+    ///
+    /// Creates the directory the output is written to, if it doesn't exist.
+    pub fn configure(&self, configuration: &Configuration) -> Result<Configuration, Error> {
+        let outdir = configuration.get("outputdir").and_then(|outdir| outdir.as_str()).map_or("output", |outdir| outdir.as_str());
+        let output = Path::new(outdir).join(&self.relative);
+
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent).map_err(|err| Error::io(&parent.to_string_lossy(), err))?;
+        }
+
+        let mut configuration = configuration.clone();
+        configuration.set("inputfile", Value::String(self.path.clone()));
+        configuration.set("outputfile", Value::String(output.to_string_lossy().into()));
+
+        if let Some(frame) = self.frame {
+            configuration.set("frame", Value::Integer(frame as i64));
+            resolve_keyframes(&mut configuration, frame);
+        }
+
+        Ok(configuration)
+    }
+}
+
+/// Finds every file of the `inputs` option, if it was specified.
+///
+/// Each input is either a file, a directory, or a glob such as `frames/*.png` or `shots/**/*.jpg`.
+/// Directories are only searched recursively if `recursive` is set. Files are sorted, and never repeated.
+///
+/// In a sequence, every file is sorted by name, numbers included, so that `frame10.png` comes after `frame9.png`.
+pub fn inputs(configuration: &Configuration) -> Result<Option<Vec<BatchInput>>, Error> {
+    let patterns = match configuration.get_inputs()? {
+        Some(patterns) => patterns,
+        None => return Ok(None),
+    };
+
+    let recursive = match configuration.get("recursive") {
+        Some(recursive) => *recursive.as_bool()
+            .ok_or_else(|| Error::Config("Must specify 'recursive' as a boolean.".into()))?,
+        None => false,
+    };
+
+    let sequence = match configuration.get("sequence") {
+        Some(sequence) => *sequence.as_bool()
+            .ok_or_else(|| Error::Config("Must specify 'sequence' as a boolean.".into()))?,
+        None => false,
+    };
+
+    let mut inputs: Vec<BatchInput> = Vec::new();
+    for pattern in patterns {
+        for input in expand(pattern, recursive)? {
+            if !inputs.iter().any(|found| found.path == input.path) {
+                inputs.push(input);
+            }
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err(Error::Config("No files were found by 'inputs'.".into()));
+    }
+
+    if sequence {
+        inputs.sort_by(|a, b| natural_order(&a.path, &b.path));
+        for (frame, input) in inputs.iter_mut().enumerate() {
+            input.frame = Some(frame);
+        }
+    }
+
+    Ok(Some(inputs))
+}
+
+/// Finds every file matched by a single input.
+fn expand(pattern: &str, recursive: bool) -> Result<Vec<BatchInput>, Error> {
+    let components: Vec<&str> = pattern.split(&['/', '\\'][..]).collect();
+
+    // The directory the glob starts from, made of every component before the first wildcard.
+    let wildcard = components.iter().position(|component| component.contains(&['*', '?'][..]));
+
+    let (base, rest) = match wildcard {
+        Some(index) => (components[..index].join("/"), &components[index..]),
+        None => {
+            let metadata = fs::metadata(pattern).map_err(|err| Error::io(pattern, err))?;
+
+            if metadata.is_dir() {
+                let files = walk(Path::new(pattern), recursive).map_err(|err| Error::io(pattern, err))?;
+                return Ok(relative_to(Path::new(pattern), files));
+            }
+
+            let relative = Path::new(pattern).file_name().map(PathBuf::from).unwrap_or_default();
+            return Ok(vec![BatchInput { path: pattern.into(), relative, frame: None }]);
+        },
+    };
+
+    let base = if base.is_empty() { PathBuf::from(".") } else { PathBuf::from(base) };
+
+    // Only globs spanning several directories need to look into them.
+    let files = walk(&base, rest.len() > 1 || rest.contains(&"**")).map_err(|err| Error::io(&base.to_string_lossy(), err))?;
+
+    let matched = files.into_iter()
+        .filter(|file| {
+            let relative = file.strip_prefix(&base).unwrap_or(file);
+            let relative: Vec<String> = relative.iter().map(|part| part.to_string_lossy().into_owned()).collect();
+            let relative: Vec<&str> = relative.iter().map(String::as_str).collect();
+            matches_path(rest, &relative)
+        })
+        .collect();
+
+    Ok(relative_to(&base, matched))
+}
+
+/// Lists every file in a directory, sorted.
+fn walk(directory: &Path, recursive: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+
+    let mut files = Vec::new();
+    for entry in entries {
+        if entry.is_dir() {
+            if recursive {
+                files.extend(walk(&entry, true)?);
+            }
+        } else {
+            files.push(entry);
+        }
+    }
+
+    Ok(files)
+}
+
+fn relative_to(base: &Path, files: Vec<PathBuf>) -> Vec<BatchInput> {
+    files.into_iter()
+        .map(|file| BatchInput {
+            relative: file.strip_prefix(base).map(Path::to_path_buf).unwrap_or_else(|_| file.clone()),
+            path: file.to_string_lossy().into(),
+            frame: None,
+        })
+        .collect()
+}
+
+/// Whether the components of a path match those of a glob, where `**` matches any number of directories.
+fn matches_path(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => matches_path(&pattern[1..], path) || (!path.is_empty() && matches_path(pattern, &path[1..])),
+        (Some(glob), Some(name)) => matches_name(glob.as_bytes(), name.as_bytes()) && matches_path(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+/// Whether a name matches a glob, where `*` matches any number of characters and `?` a single one.
+fn matches_name(glob: &[u8], name: &[u8]) -> bool {
+    match (glob.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => matches_name(&glob[1..], name) || (!name.is_empty() && matches_name(glob, &name[1..])),
+        (Some(b'?'), Some(_)) => matches_name(&glob[1..], &name[1..]),
+        (Some(a), Some(b)) => a == b && matches_name(&glob[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Compares names the way people number frames: runs of digits are compared by their value.
+fn natural_order(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+
+    loop {
+        let digits = |text: &str| text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+        let (a_digits, b_digits) = (digits(a), digits(b));
+
+        let ordering = if a_digits > 0 && b_digits > 0 {
+            let (a_number, b_number) = (a[..a_digits].trim_start_matches('0'), b[..b_digits].trim_start_matches('0'));
+            a_number.len().cmp(&b_number.len()).then_with(|| a_number.cmp(b_number))
+        } else {
+            match (a.chars().next(), b.chars().next()) {
+                (Some(a_char), Some(b_char)) => a_char.cmp(&b_char),
+                (a_char, b_char) => return a_char.cmp(&b_char),
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+
+        // Skips what was just compared, being either a number or a single character.
+        let skip = |text: &str, digits: usize| if digits > 0 { digits } else { text.chars().next().map_or(0, char::len_utf8) };
+        a = &a[skip(a, a_digits)..];
+        b = &b[skip(b, b_digits)..];
+    }
+}
+
+/// An option that changes from frame to frame, written as `{ keyframes = [[0, 100], [24, 5000]] }`.
+///
+/// Between two keyframes, the value is interpolated linearly. Before the first and after the last, it's held.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Keyframes {
+    keys: Vec<(f64, f64)>,
+    integer: bool,
+}
+
+impl Keyframes {
+    /// Parses the keyframes of an option, if it has any, returning what was expected if they're invalid.
+    pub(crate) fn parse(value: &Value) -> Option<Result<Self, String>> {
+        let invalid = || "keyframes as a list of [frame, value] pairs, such as [[0, 100], [24, 5000]]".to_string();
+
+        let keyframes = value.get("keyframes")?;
+
+        let keys = keyframes.as_array()
+            .filter(|keys| !keys.is_empty())
+            .and_then(|keys| keys.iter()
+                .map(|key| match key.as_array().map(Vec::as_slice) {
+                    Some([Value::Integer(frame), value]) if *frame >= 0 => match value {
+                        Value::Integer(int) => Some((*frame as f64, *int as f64)),
+                        Value::Float(float) => Some((*frame as f64, *float)),
+                        _ => None,
+                    },
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>());
+
+        let mut keys = match keys {
+            Some(keys) => keys,
+            None => return Some(Err(invalid())),
+        };
+        keys.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+
+        if keys.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Some(Err("keyframes with a single value for each frame".into()));
+        }
+
+        let integer = keyframes.as_array().is_some_and(|keys| keys.iter()
+            .all(|key| key.as_array().and_then(|key| key.get(1)).is_some_and(Value::is_integer)));
+
+        Some(Ok(Keyframes { keys, integer }))
+    }
+
+    /// Every value a keyframe is set to.
+    pub(crate) fn values(&self) -> impl Iterator<Item = f64> + '_ {
+        self.keys.iter().map(|(_, value)| *value)
+    }
+
+    /// Whether every keyframe is set to an integer, in which case values in between are rounded.
+    pub(crate) fn is_integer(&self) -> bool {
+        self.integer
+    }
+
+    /// The value at `frame`.
+    pub(crate) fn at(&self, frame: usize) -> Value {
+        let frame = frame as f64;
+
+        let value = match self.keys.iter().position(|(key, _)| *key > frame) {
+            Some(0) => self.keys[0].1,
+            Some(next) => {
+                let ((start, from), (end, to)) = (self.keys[next - 1], self.keys[next]);
+                from + (to - from) * (frame - start) / (end - start)
+            },
+            None => self.keys[self.keys.len() - 1].1,
+        };
+
+        if self.integer {
+            Value::Integer(value.round() as i64)
+        } else {
+            Value::Float(value)
+        }
+    }
+}
+
+/// Replaces every keyframed option, either global or under a section, by its value at `frame`.
+fn resolve_keyframes(configuration: &mut Configuration, frame: usize) {
+    let keyframed = |value: &Value| Keyframes::parse(value).and_then(Result::ok).map(|keyframes| keyframes.at(frame));
+
+    let mut resolved = Vec::new();
+    if let Some(table) = configuration.raw().as_table() {
+        for (key, value) in table {
+            match keyframed(value) {
+                Some(value) => resolved.push((key.clone(), value)),
+                None => if let Some(section) = value.as_table() {
+                    for (option, value) in section {
+                        if let Some(value) = keyframed(value) {
+                            resolved.push((format!("{}.{}", key, option), value));
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    for (key, value) in resolved {
+        configuration.set(&key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates each file under a new temporary directory, named after the test.
+    fn files(test: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("glitchup-{}-{}", test, std::process::id()));
+        for file in files {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    fn paths(inputs: &[BatchInput]) -> Vec<String> {
+        inputs.iter().map(|input| input.relative.to_string_lossy().replace('\\', "/")).collect()
+    }
+
+    #[test]
+    fn globs_match_names_and_directories() {
+        assert!(matches_name(b"*.png", b"frame.png"));
+        assert!(matches_name(b"*.png", b".png"));
+        assert!(!matches_name(b"*.png", b"frame.jpg"));
+        assert!(matches_name(b"frame?.png", b"frame1.png"));
+        assert!(!matches_name(b"frame?.png", b"frame.png"));
+        assert!(!matches_name(b"frame?.png", b"frame10.png"));
+
+        assert!(matches_path(&["**", "*.png"], &["a.png"]));
+        assert!(matches_path(&["**", "*.png"], &["shots", "one", "a.png"]));
+        assert!(!matches_path(&["*", "*.png"], &["a.png"]));
+        assert!(!matches_path(&["*.png"], &["shots", "a.png"]));
+        assert!(matches_path(&["shots", "**"], &["shots", "one", "a.png"]));
+    }
+
+    #[test]
+    fn numbers_are_sorted_by_value() {
+        assert_eq!(natural_order("frame2.png", "frame10.png"), Ordering::Less);
+        assert_eq!(natural_order("frame10.png", "frame9.png"), Ordering::Greater);
+        assert_eq!(natural_order("frame007.png", "frame7.png"), Ordering::Equal);
+        assert_eq!(natural_order("frame", "frame1"), Ordering::Less);
+        assert_eq!(natural_order("a10", "b2"), Ordering::Less);
+    }
+
+    #[test]
+    fn absolute_globs_find_every_match() {
+        let dir = files("batch-glob", &["frame10.png", "frame2.png", "notes.txt", "shots/one/a.png"]);
+
+        let pattern = |glob: &str| format!("{}/{}", dir.to_string_lossy().replace('\\', "/"), glob);
+        let flat = expand(&pattern("*.png"), false).unwrap();
+        let deep = expand(&pattern("**/*.png"), false).unwrap();
+        let sequence = inputs(&Configuration::builder()
+            .option("inputs", vec![pattern("frame*.png"), pattern("*.png")])
+            .option("sequence", true)
+            .build());
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(paths(&flat), vec!["frame10.png", "frame2.png"]);
+        assert_eq!(paths(&deep), vec!["frame10.png", "frame2.png", "shots/one/a.png"]);
+
+        let sequence = sequence.unwrap().unwrap();
+        assert_eq!(paths(&sequence), vec!["frame2.png", "frame10.png"]);
+        assert_eq!(sequence.iter().map(|input| input.frame).collect::<Vec<_>>(), vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn directories_are_mirrored_into_outputdir() {
+        let dir = files("batch-dir", &["a.png", "shots/b.png"]);
+        let outdir = dir.join("out");
+
+        let flat = expand(&dir.to_string_lossy(), false).unwrap();
+        let inputs = expand(&dir.to_string_lossy(), true).unwrap();
+        let configuration = Configuration::builder()
+            .option("outputdir", outdir.to_string_lossy().into_owned())
+            .build();
+        let configured = inputs[1].configure(&configuration).unwrap();
+        let created = outdir.join("shots").is_dir();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(paths(&flat), vec!["a.png"]);
+        assert_eq!(paths(&inputs), vec!["a.png", "shots/b.png"]);
+        assert_eq!(configured.get("inputfile").and_then(|value| value.as_str()), Some(&inputs[1].path));
+        assert_eq!(
+            configured.get("outputfile").and_then(|value| value.as_str()),
+            Some(&outdir.join("shots").join("b.png").to_string_lossy().into_owned()),
+        );
+        assert!(created);
+    }
+
+    fn keyframes(keys: &str) -> Option<Result<Keyframes, String>> {
+        let table: Value = toml::from_str(&format!("option = {{ keyframes = {} }}", keys)).unwrap();
+        Keyframes::parse(&table["option"])
+    }
+
+    #[test]
+    fn keyframes_are_held_and_interpolated() {
+        let keys = keyframes("[[10, 100], [20, 200], [30, 0]]").unwrap().unwrap();
+
+        assert_eq!(keys.at(0), Value::Integer(100));
+        assert_eq!(keys.at(10), Value::Integer(100));
+        assert_eq!(keys.at(14), Value::Integer(140));
+        assert_eq!(keys.at(25), Value::Integer(100));
+        assert_eq!(keys.at(30), Value::Integer(0));
+        assert_eq!(keys.at(1000), Value::Integer(0));
+    }
+
+    #[test]
+    fn keyframes_with_floats_are_not_rounded() {
+        let keys = keyframes("[[0, 0], [4, 1.0]]").unwrap().unwrap();
+
+        assert!(!keys.is_integer());
+        assert_eq!(keys.at(1), Value::Float(0.25));
+    }
+
+    #[test]
+    fn unsorted_keyframes_are_sorted() {
+        let keys = keyframes("[[20, 200], [0, 0], [10, 50]]").unwrap().unwrap();
+
+        assert_eq!(keys.values().collect::<Vec<_>>(), vec![0.0, 50.0, 200.0]);
+        assert_eq!(keys.at(5), Value::Integer(25));
+        assert_eq!(keys.at(15), Value::Integer(125));
+    }
+
+    #[test]
+    fn invalid_keyframes_are_rejected() {
+        for keys in &["[]", "[[0, 'fast']]", "[[0]]", "[[-1, 5]]", "[[1.5, 5]]", "[[0, 1], [0, 2]]", "5"] {
+            assert!(matches!(keyframes(keys), Some(Err(_))), "{}", keys);
+        }
+
+        assert_eq!(Keyframes::parse(&Value::Integer(5)), None);
+    }
+
+    #[test]
+    fn keyframes_resolve_globally_and_in_sections() {
+        let mut configuration = Configuration::builder()
+            .option("chunksize", toml::from_str::<Value>("keyframes = [[0, 10], [10, 110]]").unwrap())
+            .option("LoopsConfig.loops", toml::from_str::<Value>("keyframes = [[0, 1.0], [10, 2.0]]").unwrap())
+            .option("iterations", 3)
+            .build();
+
+        resolve_keyframes(&mut configuration, 5);
+
+        assert_eq!(configuration.get("chunksize").and_then(|value| value.as_int()), Some(&60));
+        assert_eq!(configuration.get_option("LoopsConfig", "loops").and_then(|value| value.as_float()), Some(&1.5));
+        assert_eq!(configuration.get("iterations").and_then(|value| value.as_int()), Some(&3));
+    }
+}
+
@@ -1,153 +1,449 @@
-use super::{loaders::Loader};
+use super::loaders::{Loader, STREAM};
 
 use memmap::MmapMut;
+use rand_core::{RngCore, SeedableRng};
 
 use super::mutations::*;
+use super::error::Error;
 
 use super::configuration::Configuration;
+use super::replay::{Recipe, Timing};
 
-use std::collections::HashMap;
+use super::registry;
+use super::formats::{self, Decoded};
+use super::naming;
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use rayon::prelude::*;
-use lazy_static::lazy_static;
-
-type Mut = fn(&mut [u8], &Configuration) -> Result<String, MutationError>;
-type Muts = Vec<Mut>;
-
-// Initialise all mutations in here.
-lazy_static! {
-    pub static ref MUTMAP: HashMap<String, Mut> = {
-        let mut map = HashMap::new();
-        let mutmap: Vec<(String, Mut)> = 
-        vec![
-            ("Void".into()     , void),
-            ("Chaos".into()    , chaos),
-            ("Loops".into()    , loops),
-            ("Reverse".into()  , reverse),
-            ("Shift".into()    , shift),
-            ("Shuffle".into()  , shuffle),
-            ("Swap".into()     , swap),
-            ("Increase".into() , increase),
-            ("Gradient".into() , gradient),
-            ("Multiply".into() , multiply),
-            ("Compress".into() , compress),
-            // Add more mutations here.
-        ];
-
-        for (k,v) in mutmap.into_iter() {
-            map.insert(k, v);
-        }
 
-        map
+/// A list of mutations, each along with the configuration it uses.
+type Steps<'c> = Vec<(Arc<dyn Mutation>, &'c Configuration)>;
+
+/// The data a list of mutations works on.
+enum Buffer<'d> {
+    /// A memory-mapped file, which can't change size.
+    Mapped(&'d mut MmapMut),
+    /// Data in memory, which can.
+    Resizable(&'d mut Vec<u8>),
+}
+
+/// A list of mutations once applied, before it's saved.
+struct Applied {
+    /// The log of every mutation applied.
+    log: Vec<String>,
+    /// What each mutation did, if it was recorded for manifests.
+    timings: Vec<Timing>,
+    /// The data bent in memory, or `None` if it's already in its temporary file.
+    data: Option<Vec<u8>>,
+}
+
+/// The result of bending data in memory.
+pub struct Bent {
+    /// The mutated data.
+    pub data: Vec<u8>,
+    /// The log of every mutation applied, such as `SFT_it=42_ch=1200`.
+    pub log: Vec<String>,
+    /// The seed that generated this result. Can be used to replay it.
+    pub seed: u64,
+}
+
+/// Bends data in memory, once for each list of mutations in the configuration.
+/// 
+/// Uses the `seed` option if specified, or a random seed otherwise.
+pub fn bend_bytes(configuration: &Configuration, data: &[u8]) -> Result<Vec<Bent>, Error> {
+    let seed = configuration.get_seed()?.unwrap_or_else(rand::random);
+
+    // Decodes the data first, if its format requires it.
+    let decoded = formats::decode(configuration, data)?;
+    let source = decoded.as_ref().map_or(data, |decoded| decoded.data());
+    let derived = formats::derived_options(configuration, decoded.as_deref());
+
+    mutation_lists(configuration, seed)?
+        .into_par_iter()
+        .enumerate()
+        .map(|(index, steps)| {
+            let mut data = source.to_vec();
+            let seed = list_seed(seed, index);
+            let log = apply_mutations(Buffer::Resizable(&mut data), steps, seed, &derived, false)?.log;
+
+            let data = match &decoded {
+                Some(decoded) => decoded.encode(formats::fit(data, decoded.data()), configuration)?,
+                None => data,
+            };
+
+            Ok(Bent { data, log, seed })
+        })
+        .collect()
+}
+
+/// Reads a file and bends it in memory, as in `bend_bytes`.
+pub fn bend_file(configuration: &Configuration, path: &str) -> Result<Vec<Bent>, Error> {
+    let data = Loader::read_file(path).map_err(|err| Error::io(path, err))?;
+    bend_bytes(configuration, &data)
+}
+
+/// The lists of mutations a run applies: either the `mutations` option, or a single chain drawn from `pool`.
+fn mutation_lists(config: &Configuration, seed: u64) -> Result<Vec<Steps<'_>>, Error> {
+    let lists: Vec<Vec<String>> = match config.get_pool()? {
+        Some(pool) => vec![draw_chain(&pool, config.get_chain_length()?, seed)],
+        None => config.get_mutations()?.into_iter()
+            .map(|list| list.into_iter().cloned().collect())
+            .collect(),
     };
+
+    lists.iter().map(|list| {
+        list.iter().map(|name| {
+            registry::get(name)
+                .map(|mutation| (mutation, config))
+                .ok_or_else(|| Error::Config(format!("Invalid mutation: {:?}", name)))
+        }).collect()
+    }).collect()
+}
+
+/// Draws a chain of mutations from a pool, where each mutation is picked in proportion to its weight.
+/// 
+/// The chain is drawn from its own RNG, so the same pool and seed always give the same chain.
+fn draw_chain(pool: &[(&String, u64)], (min, max): (usize, usize), seed: u64) -> Vec<String> {
+    let mut rng = MutRng::seed_from_u64(seed ^ 0xC4A1_4C4A_14C4_A14C);
+    let total: u64 = pool.iter().map(|(_, weight)| weight).sum();
+
+    let length = min + (rng.next_u64() % (max - min + 1) as u64) as usize;
+
+    (0..length).map(|_| {
+        let mut draw = rng.next_u64() % total;
+        for (name, weight) in pool {
+            if draw < *weight {
+                return name.to_string();
+            }
+            draw -= weight;
+        }
+        unreachable!("The draw is always lower than the total weight.")
+    }).collect()
+}
+
+/// Applies each mutation in order, all drawing from a single RNG seeded by `seed`.
+/// 
+/// `derived` holds the options describing the data, such as `align`, used by every mutation that doesn't set its own.
+/// If `record` is set, how long each mutation took and which bytes it changed are recorded as well,
+/// which needs a copy of the data before each one.
+fn apply_mutations(mut data: Buffer, steps: Steps, seed: u64, derived: &[(String, toml::Value)], record: bool) -> Result<Applied, Error> {
+    let mut rng = MutRng::seed_from_u64(seed);
+    let mut applied = Applied { log: Vec::new(), timings: Vec::new(), data: None };
+
+    for (mutation, config) in steps {
+        let mut config = config.with_defaults(&*mutation);
+        for (key, value) in derived {
+            if config.get(key).is_none() {
+                config.to_mut().set(key, value.clone());
+            }
+        }
+
+        let before = if record { Some(data.as_slice().to_vec()) } else { None };
+        let start = Instant::now();
+
+        let log = match &mut data {
+            Buffer::Mapped(map) => mutation.apply(map, &config, &mut rng),
+            Buffer::Resizable(vec) => mutation.apply_resizable(vec, &config, &mut rng),
+        }?;
+
+        if let Some(before) = before {
+            applied.timings.push(Timing {
+                millis: start.elapsed().as_secs_f64() * 1000.0,
+                touched: touched(&before, data.as_slice()),
+            });
+        }
+        applied.log.push(log);
+    }
+
+    Ok(applied)
+}
+
+impl Buffer<'_> {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Buffer::Mapped(map) => map,
+            Buffer::Resizable(vec) => vec,
+        }
+    }
+}
+
+/// The ranges of bytes that differ between `before` and `after`, as `[start, end]` offsets into `after`.
+/// 
+/// Changes closer than 16 bytes are merged, and at most 1000 ranges are kept, the last one covering every other change.
+/// If the size changed, this is a single range from the first byte that differs to the last.
+fn touched(before: &[u8], after: &[u8]) -> Vec<[usize; 2]> {
+    const GAP: usize = 16;
+    const MAX_RANGES: usize = 1000;
+
+    if before.len() != after.len() {
+        let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let suffix = before.iter().rev().zip(after.iter().rev())
+            .take(before.len().min(after.len()) - prefix)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        return vec![[prefix, after.len() - suffix]];
+    }
+
+    let mut ranges: Vec<[usize; 2]> = Vec::new();
+    for index in (0..after.len()).filter(|&index| before[index] != after[index]) {
+        let full = ranges.len() == MAX_RANGES;
+        match ranges.last_mut() {
+            Some(last) if index <= last[1] + GAP || full => last[1] = index + 1,
+            _ => ranges.push([index, index + 1]),
+        }
+    }
+
+    ranges
+}
+
+/// Prints an error as it happens, so that it's seen even while other lists are still being bent.
+fn report(error: Error) -> Error {
+    eprintln!("{}", error);
+    error
+}
+
+/// Whether any mutation in the list changes the size of the data.
+fn resizes(steps: &Steps) -> bool {
+    steps.iter().any(|(mutation, _)| mutation.resizes())
+}
+
+/// Derives the seed used by the list of mutations at `index`.
+/// 
+/// The first list uses the run's seed as-is, so a single list can be reproduced
+/// by setting `seed` to the value shown in its output name.
+fn list_seed(seed: u64, index: usize) -> u64 {
+    seed.wrapping_add((index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
 }
 
 /// A main controller of the databender.
 /// 
 /// Manages the file handling, data storage, and controls mutations.
+/// 
+/// By default, each list of mutations works on a memory-mapped copy of the input next to the output.
+/// With `backend = "memory"`, or when reading from stdin or writing to stdout, everything is bent in memory
+/// instead, and nothing but the output is written.
 pub struct KaBender<'a> {
-    pub seed: String,
+    pub seed: u64,
+    input: String,
     outdir: String,
     extension: String,
     output: String,
+    in_memory: bool,
+    run: usize,
+    input_crc32: Option<String>,
+    records: Option<&'a Mutex<Vec<Recipe>>>,
     pub config: &'a Configuration,
 }
 
 impl<'a> KaBender<'a> {
     /// Creates a new KaBender from the configuration.
-    pub fn new(configuration: &'a Configuration, seed: String) -> Self {
-        println!("Initialising bender...");
+    /// 
+    /// * `seed` - The seed of this run. Every random choice made by the bender derives from it.
+    pub fn new(configuration: &'a Configuration, seed: u64) -> Result<Self, Error> {
+        eprintln!("Initialising bender...");
         let mut new : KaBender = KaBender {
-            seed: seed,
+            seed,
             config : configuration,
+            input : String::new(),
             extension : String::new(),
             output : String::new(),
             outdir : String::new(),
+            in_memory : false,
+            run : 0,
+            input_crc32 : None,
+            records : None,
         };
 
-        new.setup_file_data();
-        new
+        new.setup_file_data()?;
+        Ok(new)
+    }
+
+    /// Sets which of the `times` this is, used by the `{run}` placeholder of `output_template`.
+    pub fn with_run(mut self, run: usize) -> Self {
+        self.run = run;
+        self
+    }
+
+    /// Adds the recipe of every output to `records`, along with everything its manifest records.
+    /// Used to write a manifest of a whole run.
+    pub fn with_records(mut self, records: &'a Mutex<Vec<Recipe>>) -> Self {
+        self.records = Some(records);
+        self
+    }
+
+    /// Whether each output gets a manifest, either of its own or in `records`.
+    fn recording(&self) -> bool {
+        self.records.is_some() || self.config.get("manifest").and_then(|manifest| manifest.as_bool()).cloned().unwrap_or(false)
+    }
+
+    /// Hashes the input for the manifests, so that outputs can be traced back to it even if it moves.
+    fn hash_input(&mut self) {
+        if self.recording() {
+            self.input_crc32 = Loader::read_file(&self.input).ok()
+                .map(|data| format!("{:08x}", crc32fast::hash(&data)));
+        }
     }
 
     /// Executes the bender.
     /// 
     /// Performs all mutation combinations using the configuration loaded.
-    pub fn run(mut self) {
-        // Retrieves mutations.
-        let mutations = self.config.get_mutations();
-
-        // Generates a file for each list of mutations
-        let filelist : Vec<MmapMut> = self.init_file_n(mutations.len());
-
-        // Retrieves all mutations from hashmap using the file.
-        let mutations : Vec<Muts> = mutations.par_iter().map(|combo| {
-            combo.iter().map(|mut_str| {
-                MUTMAP.get(*mut_str).cloned().unwrap()
-            }).collect()
-        }).collect();
-
-        // Pairs each memory-mapped file to a list of mutations.
-        let mut mut_map : Vec<(Muts, MmapMut)> = mutations
-            .into_iter()
-            .zip(filelist
-                .into_iter())
-            .collect();
-
-        // Performs the mutations in parallel
-        mut_map
-            .par_iter_mut()
-            .enumerate()
-            .for_each(|(index, (mutation_combo, map))| {
-                let mut log = Vec::new();
-
-                let results: Result<Vec<_>, _> = mutation_combo.into_iter().map(|mutation| {
-                    match mutation(map, self.config) {
-                        Ok(mutation) => Ok(log.push(mutation)),
-                        Err(error) => {
-                            eprintln!("{}", error.error);
-                            Loader::remove_file(&format!("{}temp{}SEED={}.{}", self.outdir, index, self.seed, self.extension))
-                        },
-                    }
-                }).collect();
-
-                if results.is_ok() {
-                    self.flush(index, log);
-                }
+    /// Errors are printed as they happen, and a failing list doesn't stop the others.
+    /// Returns the first error, once every list is done.
+    pub fn run(mut self) -> Result<(), Error> {
+        // Retrieves all mutations from the registry, drawing a chain from the pool if there is one.
+        let lists = mutation_lists(self.config, self.seed).map_err(report)?;
+        self.hash_input();
+
+        self.bend_lists(lists)
+    }
+
+    /// Replays a recipe on the input file.
+    /// 
+    /// Every mutation uses the exact options stored in the recipe, rather than the configuration's.
+    /// Errors are printed as they happen, like in `run`.
+    pub fn replay(mut self, recipe: &Recipe) -> Result<(), Error> {
+        self.hash_input();
+
+        // Fixes the options of each mutation separately, in case a mutation is used more than once.
+        let configs : Vec<(Arc<dyn Mutation>, Configuration)> = recipe.mutations.iter().map(|step| {
+            registry::get(&step.name)
+                .map(|mutation| (mutation, step.to_configuration(self.config)))
+                .ok_or_else(|| report(Error::Config(format!("Invalid mutation: {:?}", step.name))))
+        }).collect::<Result<_, _>>()?;
+
+        let steps = configs.iter().map(|(mutation, config)| (mutation.clone(), config)).collect();
+        self.bend_lists(vec![steps])
+    }
+
+    /// Applies each list of mutations in parallel, each to its own copy of the input.
+    fn bend_lists(&self, lists: Vec<Steps>) -> Result<(), Error> {
+        // Decodes the input first, if its format requires it.
+        let decoded = formats::decode_input(self.config).map_err(report)?;
+        let derived = formats::derived_options(self.config, decoded.as_deref());
+
+        let results: Vec<Result<(), Error>> = match decoded {
+            Some(decoded) => {
+                lists
+                    .into_par_iter()
+                    .enumerate()
+                    .map(|(index, steps)| self.bend_decoded(index, &*decoded, steps, &derived))
+                    .collect()
+            },
+            None => {
+                eprintln!("Initialising file...");
+
+                // Performs the mutations in parallel, each on its own copy of the file.
+                // Lists that change the size of the file can't be memory-mapped, so they're bent in memory.
+                lists
+                    .into_par_iter()
+                    .enumerate()
+                    .map(|(index, steps)| {
+                        if self.in_memory || resizes(&steps) {
+                            self.bend_in_memory(index, steps, &derived)
+                        } else {
+                            match self.init_file(index) {
+                                Ok(mut map) => self.bend(index, &mut map, steps, &derived),
+                                Err(err) => Err(report(Error::io(&self.input, err))),
+                            }
+                        }
+                    })
+                    .collect()
+            },
+        };
+
+        results.into_iter().collect()
+    }
+
+    /// Applies a list of mutations to a file, then saves it.
+    /// 
+    /// * `index` - The index of the list of mutations.
+    /// * `map` - The memory-mapped file to mutate.
+    /// * `steps` - Each mutation to apply, along with the configuration it uses.
+    /// * `derived` - The options describing the data, used by mutations that don't set their own.
+    fn bend(&self, index: usize, map: &mut MmapMut, steps: Steps, derived: &[(String, toml::Value)]) -> Result<(), Error> {
+        let result = apply_mutations(Buffer::Mapped(map), steps, list_seed(self.seed, index), derived, self.recording());
+        self.finish(index, result)
+    }
+
+    /// Applies a list of mutations to a copy of the file in memory, then saves it.
+    /// 
+    /// Used when the mutations change the size of the file, or with the memory backend.
+    fn bend_in_memory(&self, index: usize, steps: Steps, derived: &[(String, toml::Value)]) -> Result<(), Error> {
+        let result = Loader::read_file(&self.input)
+            .map_err(|err| Error::io(&self.input, err))
+            .and_then(|mut data| {
+                let applied = apply_mutations(Buffer::Resizable(&mut data), steps, list_seed(self.seed, index), derived, self.recording())?;
+                Ok(Applied { data: Some(data), ..applied })
             });
+
+        self.finish(index, result)
     }
 
-    /// Initialises multiple memory mapped copies of a file.
+    /// Applies a list of mutations to a copy of decoded data, then encodes and saves it.
+    fn bend_decoded(&self, index: usize, decoded: &dyn Decoded, steps: Steps, derived: &[(String, toml::Value)]) -> Result<(), Error> {
+        let mut data = decoded.data().to_vec();
+
+        let result = apply_mutations(Buffer::Resizable(&mut data), steps, list_seed(self.seed, index), derived, self.recording())
+            .and_then(|applied| {
+                let file = decoded.encode(formats::fit(data, decoded.data()), self.config)?;
+                Ok(Applied { data: Some(file), ..applied })
+            });
+
+        self.finish(index, result)
+    }
+
+    /// Saves the output if mutating succeeded, or reports the error and removes it otherwise.
     /// 
-    /// * `n` - Number of files to initialize
-    fn init_file_n(&mut self, n: usize) -> Vec<MmapMut> {
-        println!("Initialising file...");
-
-        (0..n)
-            .into_par_iter()
-            .map(|index| {
-                Loader::init_file_mut(
-                    self.config.get_inputfile(),
-                    format!("{}temp{}SEED={}.{}",
-                        self.outdir,
-                        index,
-                        self.seed,
-                        self.extension
-                    ).as_str()
-                ).unwrap()
-            }).collect()
+    /// Data bent in memory is given along with the log, while memory-mapped data is already in its temporary file.
+    fn finish(&self, index: usize, result: Result<Applied, Error>) -> Result<(), Error> {
+        match result {
+            Ok(applied) => self.flush(index, applied).map_err(report),
+            Err(error) => {
+                if !self.in_memory {
+                    let _ = Loader::remove_file(&self.temporary_name(index));
+                }
+                Err(report(error))
+            },
+        }
+    }
+
+    /// The name of the temporary file used by the list of mutations at `index`.
+    fn temporary_name(&self, index: usize) -> String {
+        format!("{}temp{}SEED={}.{}", self.outdir, index, self.seed, self.extension)
+    }
+
+    /// Initialises a memory mapped copy of the file, used by the list of mutations at `index`.
+    fn init_file(&self, index: usize) -> std::io::Result<MmapMut> {
+        Loader::init_file_mut(
+            &self.input,
+            &self.temporary_name(index)
+        )
     }
 
     /// Sets up the data of the file, such as the input, output, extension, and path.
-    fn setup_file_data(&mut self) {
+    fn setup_file_data(&mut self) -> Result<(), Error> {
         use std::path::Path;
         use std::ffi::OsStr;
 
-        let input = self.config.get_inputfile();
+        let input = self.config.get_inputfile()?;
+        self.input = input.to_string();
 
         // Sets output name to custom name, or input if not specified.
         let output: &str = self.config.get("outputfile")
             .and_then(|v| v.as_str())
-            .map_or(input.clone(), |s| s.as_str());
+            .map_or(input, |s| s.as_str());
+
+        // Streams can't be memory-mapped, and nothing else gets written.
+        let backend = self.config.get("backend").and_then(|backend| backend.as_str()).map_or("mmap", |backend| backend.as_str());
+        self.in_memory = backend == "memory" || input == STREAM || output == STREAM;
+
+        if output == STREAM {
+            self.output = STREAM.into();
+            return Ok(());
+        }
 
         let path = Path::new(&output);
 
@@ -155,13 +451,12 @@ impl<'a> KaBender<'a> {
         self.extension = String::from(path
             .extension()
             .and_then(OsStr::to_str)
-            .unwrap()
-            .clone());
+            .ok_or_else(|| Error::Config(format!("The output '{}' must have an extension.", output)))?);
 
         // Extracts the output directory.
         // In X/Y.../Z.EXT, this extracts X/Y.../
         self.outdir = path.parent().and_then(Path::to_str).map_or(String::new(), |text| {
-            if text == "" {
+            if text.is_empty() {
                 String::new()
             } else {
                 format!("{}/", text)
@@ -173,36 +468,249 @@ impl<'a> KaBender<'a> {
         self.output = format!(
             "{}{}",
             self.outdir,
-            path.file_stem().and_then(OsStr::to_str).unwrap().clone(),
+            path.file_stem().and_then(OsStr::to_str)
+                .ok_or_else(|| Error::Config(format!("The output '{}' must have a file name.", output)))?,
         );
+
+        Ok(())
     }
 
-    /// Renames the temporary file that was mutated to its supposed output file.
+    /// Renames the temporary file that was mutated to its supposed output file,
+    /// or writes the data bent in memory to it.
     /// 
     /// * `iter` - The iteration. Used to rename the right mutated file.
-    /// * `log` - The log of mutations applied to the file. Used to embed mutation data into the filename itself.
-    fn flush(&self, iter: usize, log: Vec<String>){
-        let mut temp_muts = log.join("---");
-        if temp_muts.len() > 200 {
-            temp_muts.truncate(200);
-            println!("Truncating mutation name due to length...");
+    /// * `applied` - The log of mutations applied to the file, used to embed mutation data into the filename itself,
+    ///   along with the data bent in memory if it wasn't memory-mapped.
+    fn flush(&self, iter: usize, applied: Applied) -> Result<(), Error> {
+        let Applied { log, timings, data } = applied;
+
+        // Streams have no name, so the mutations are only shown.
+        if self.output == STREAM {
+            Loader::write_output(STREAM, &data.unwrap_or_default())
+                .map_err(|err| Error::io("stdout", err))?;
+            eprintln!("SEED={}__{}", list_seed(self.seed, iter), log.join("---"));
+            self.record(iter, STREAM, &log, timings);
+            return Ok(());
         }
 
-        // Generates an output name
-        let genoutput = format!("{name}__{muts}.{ext}",
-            name = self.output.clone(),
-            muts = temp_muts,
-            ext = self.extension.clone(),
-        );
+        let genoutput = match self.config.get("output_template").and_then(|template| template.as_str()) {
+            Some(template) => {
+                let fields = naming::Fields {
+                    stem: &self.output[self.outdir.len()..],
+                    ext: &self.extension,
+                    run: self.run,
+                    index: iter,
+                    seed: list_seed(self.seed, iter),
+                    log: &log,
+                };
+                let name = naming::render(template, &fields).map_err(Error::Config)?;
+                let name = naming::fit(format!("{}{}", self.outdir, name), &fields);
+
+                // Templates can put outputs in directories of their own.
+                if let Some(parent) = std::path::Path::new(&name).parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                    std::fs::create_dir_all(parent).map_err(|err| Error::io(&parent.to_string_lossy(), err))?;
+                }
+                name
+            },
+            None => {
+                let mut temp_muts = log.join("---");
+                if temp_muts.len() > 200 {
+                    temp_muts.truncate(200);
+                    eprintln!("Truncating mutation name due to length...");
+                }
+
+                // Generates an output name
+                format!("{name}__SEED={seed}__{muts}.{ext}",
+                    name = self.output.clone(),
+                    seed = list_seed(self.seed, iter),
+                    muts = temp_muts,
+                    ext = self.extension.clone(),
+                )
+            },
+        };
+
+        // Outputs with the same name are skipped, replaced, or written next to each other.
+        let collision = self.config.get("on_collision")
+            .and_then(|collision| collision.as_str())
+            .and_then(|collision| naming::Collision::parse(collision))
+            .unwrap_or(naming::Collision::Suffix);
+
+        let genoutput = match collision.claim(&genoutput).map_err(|err| Error::io(&genoutput, err))? {
+            Some(genoutput) => genoutput,
+            None => {
+                eprintln!("Skipping '{}', which already exists.", genoutput);
+                if data.is_none() {
+                    let _ = Loader::remove_file(&self.temporary_name(iter));
+                }
+                return Ok(());
+            },
+        };
+
+        let written = match data {
+            Some(data) => Loader::write_file(&genoutput, &data),
+            None => {
+                let temporaryname = self.temporary_name(iter);
+
+                // Renames temporary file to actual output name
+                Loader::rename_file(&temporaryname, &genoutput)
+            },
+        };
+
+        if let Err(err) = written {
+            // Doesn't leave behind the empty file the name was claimed with.
+            if collision != naming::Collision::Overwrite {
+                let _ = Loader::remove_file(&genoutput);
+            }
+            return Err(Error::io(&genoutput, err));
+        }
+
+        self.record(iter, &genoutput, &log, timings);
+
+        Ok(())
+    }
+
+    /// Writes a manifest next to the output, so that it can be replayed even if its name was truncated,
+    /// and adds it to the manifest of the run.
+    /// 
+    /// Besides the recipe, manifests hold the hash of the input, the exact configuration used,
+    /// and how long each mutation took along with the bytes it changed.
+    fn record(&self, iter: usize, output: &str, log: &[String], timings: Vec<Timing>) {
+        if !self.recording() {
+            return;
+        }
+
+        let manifest = format!("{}.json", output);
+        let recipe = Recipe::from_log(&self.input, list_seed(self.seed, iter), log).map(|mut recipe| {
+            recipe.output = Some(output.to_string());
+            recipe.input_crc32 = self.input_crc32.clone();
+            recipe.frame = self.config.get("frame").and_then(|frame| frame.as_int()).map(|frame| *frame as u64);
+            recipe.millis = Some(timings.iter().map(|timing| timing.millis).sum());
+            recipe.config = Some(self.config.raw().clone());
+
+            for (step, timing) in recipe.mutations.iter_mut().zip(timings) {
+                step.timing = Some(timing);
+            }
+            recipe
+        });
+
+        let recipe = match recipe {
+            Ok(recipe) => recipe,
+            Err(err) => return eprintln!("Couldn't write the manifest '{}': {}", manifest, err),
+        };
+
+        if self.config.get("manifest").and_then(|manifest| manifest.as_bool()).cloned().unwrap_or(false) {
+            if let Err(err) = recipe.save(&manifest) {
+                eprintln!("Couldn't write the manifest '{}': {}", manifest, err);
+            }
+        }
 
-        let temporaryname = format!("{}temp{}SEED={}.{}", self.outdir, iter, self.seed, self.extension);
+        if let Some(records) = self.records {
+            if let Ok(mut records) = records.lock() {
+                records.push(recipe);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Renames temporary file to actual output name
-        let result = Loader::rename_file(&temporaryname, &genoutput);
+    fn sample() -> Vec<u8> {
+        (0..8192u32).map(|index| (index * 31 % 251) as u8).collect()
+    }
 
-        if let Err(err) = result {
-            println!("\n{:-^80}\nSomething went wrong while renaming the file from \n{} to {}\n{}\n{:-^80}", "ERROR",
-             temporaryname, genoutput, err.to_string(), "")
+    #[test]
+    fn same_seed_gives_same_output() {
+        let config = Configuration::builder()
+            .mutations(&[&["Shift", "Chaos", "Reverse"], &["Loops", "Swap"], &["Insert", "Delete"]])
+            .option("iterations", vec![1, 10])
+            .option("chunksize", vec![10, 500])
+            .option("loops", vec![1, 5])
+            .seed(42)
+            .build();
+        let data = sample();
+
+        let first = bend_bytes(&config, &data).unwrap();
+        let second = bend_bytes(&config, &data).unwrap();
+
+        assert_eq!(first.len(), 3);
+        for (first, second) in first.iter().zip(&second) {
+            assert_eq!(first.seed, second.seed);
+            assert_eq!(first.log, second.log);
+            assert_eq!(first.data, second.data);
+            assert_ne!(first.data, data);
         }
     }
+
+    #[test]
+    fn different_seeds_give_different_outputs() {
+        let build = |seed| Configuration::builder()
+            .mutations(&[&["Chaos"]])
+            .option("iterations", 5)
+            .option("chunksize", 100)
+            .seed(seed)
+            .build();
+        let data = sample();
+
+        let first = bend_bytes(&build(1), &data).unwrap();
+        let second = bend_bytes(&build(2), &data).unwrap();
+
+        assert_ne!(first[0].data, second[0].data);
+    }
+
+    #[test]
+    fn replaying_an_output_name_gives_the_same_output() {
+        let config = Configuration::builder()
+            .mutations(&[&["Shift", "Chaos"], &["Loops", "Duplicate"]])
+            .option("iterations", vec![1, 10])
+            .option("chunksize", vec![10, 500])
+            .option("loops", vec![1, 5])
+            .seed(7)
+            .build();
+        let data = sample();
+
+        for bent in bend_bytes(&config, &data).unwrap() {
+            let name = format!("out__SEED={}__{}~2.bin", bent.seed, bent.log.join("---"));
+            let recipe = Recipe::from_filename(&name).unwrap();
+            assert_eq!(recipe.seed, bent.seed);
+
+            // Replays the recipe the way `KaBender::replay` does.
+            let base = recipe.apply_to(&config);
+            let configs: Vec<(Arc<dyn Mutation>, Configuration)> = recipe.mutations.iter()
+                .map(|step| (registry::get(&step.name).unwrap(), step.to_configuration(&base)))
+                .collect();
+            let steps = configs.iter().map(|(mutation, config)| (mutation.clone(), config)).collect();
+
+            let mut replayed = data.clone();
+            let applied = apply_mutations(Buffer::Resizable(&mut replayed), steps, recipe.seed, &[], false).unwrap();
+
+            assert_eq!(applied.log, bent.log);
+            assert_eq!(replayed, bent.data);
+        }
+    }
+
+    #[test]
+    fn failed_outputs_leave_no_claimed_file() {
+        let dir = std::env::temp_dir().join(format!("glitchup-flush-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.bin");
+        std::fs::write(&input, sample()).unwrap();
+
+        let config = Configuration::builder()
+            .mutations(&[&["Reverse"]])
+            .option("inputfile", input.to_string_lossy().into_owned())
+            .option("output_template", "out.{ext}")
+            .seed(3)
+            .build();
+        let bender = KaBender::new(&config, 3).unwrap();
+
+        // The temporary file was never written, so renaming it fails.
+        let applied = Applied { log: vec!["REV".into()], timings: Vec::new(), data: None };
+        assert!(bender.flush(0, applied).is_err());
+        let claimed = dir.join("out.bin").exists();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!claimed);
+    }
 }
@@ -0,0 +1,188 @@
+use glitchup::{Configuration, Error};
+
+/// The usage shown by `--help`, or when the arguments are invalid.
+pub const USAGE: &str = "\
+Usage: glitchup [OPTIONS] [KEY=VALUE]...
+
+Options:
+    -c, --config <PATH>     Options file to use [default: Options.toml]
+    -i, --input <PATH>      File to databend (overrides 'inputfile'). '-' reads stdin
+    -o, --output <PATH>     Output file (overrides 'outputfile'). '-' writes to stdout
+    -t, --times <N>         How many times to run (overrides 'times')
+    -s, --seed <N>          Seed to use (overrides 'seed')
+    -m, --mutation <NAMES>  A list of mutations, separated by commas. Can be repeated,
+                            and replaces 'mutations' entirely
+    -h, --help              Shows this message
+
+A lone '-' reads stdin and writes to stdout, unless '-o' is given, as in
+'cat in.jpg | glitchup - > out.jpg'.
+
+Any KEY=VALUE is merged on top of the options file. VALUE is read as TOML,
+and sections can be accessed with a dot, such as 'LoopsConfig.loops=[2,5]'.";
+
+/// The arguments passed to the executable.
+pub struct Cli {
+    /// The options file, if given explicitly.
+    pub config: Option<String>,
+    /// Every option that overrides the options file, in order.
+    pub overrides: Vec<(String, toml::Value)>,
+    /// Whether `--help` was passed.
+    pub help: bool,
+}
+
+impl Cli {
+    /// Parses the arguments, excluding the name of the executable.
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let mut cli = Cli { config: None, overrides: Vec::new(), help: false };
+        let mut mutations = Vec::new();
+        let mut stream = false;
+
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| format!("Missing value for '{}'.", flag));
+
+            match arg.as_str() {
+                "-h" | "--help" => cli.help = true,
+                "-" => {
+                    cli.overrides.push(("inputfile".into(), toml::Value::String(arg.clone())));
+                    stream = true;
+                },
+                "-c" | "--config" => cli.config = Some(value(&arg)?),
+                "-i" | "--input" => cli.overrides.push(("inputfile".into(), toml::Value::String(value(&arg)?))),
+                "-o" | "--output" => cli.overrides.push(("outputfile".into(), toml::Value::String(value(&arg)?))),
+                "-t" | "--times" => {
+                    let times = value(&arg)?.parse()
+                        .map_err(|_| format!("'{}' expects an integer.", arg))?;
+                    cli.overrides.push(("times".into(), toml::Value::Integer(times)));
+                },
+                "-s" | "--seed" => {
+                    let seed: u64 = value(&arg)?.parse()
+                        .map_err(|_| format!("'{}' expects a positive integer.", arg))?;
+                    cli.overrides.push(("seed".into(), toml::Value::Integer(seed as i64)));
+                },
+                "-m" | "--mutation" => {
+                    let list = value(&arg)?.split(',')
+                        .map(|name| toml::Value::String(name.trim().into()))
+                        .collect();
+                    mutations.push(toml::Value::Array(list));
+                },
+                _ => {
+                    let mut pair = arg.splitn(2, '=');
+                    match (pair.next(), pair.next()) {
+                        (Some(key), Some(value)) if !key.is_empty() && !key.starts_with('-') => {
+                            cli.overrides.push((key.into(), Cli::parse_value(value)));
+                        },
+                        _ => return Err(format!("Unexpected argument '{}'.", arg)),
+                    }
+                },
+            }
+        }
+
+        // A lone '-' also writes to stdout, unless given another output.
+        if stream && !cli.overrides.iter().any(|(key, _)| key == "outputfile") {
+            cli.overrides.push(("outputfile".into(), toml::Value::String("-".into())));
+        }
+
+        if !mutations.is_empty() {
+            cli.overrides.push(("mutations".into(), toml::Value::Array(mutations)));
+        }
+
+        Ok(cli)
+    }
+
+    /// Parses a value as TOML, treating it as a plain string if it isn't valid.
+    fn parse_value(value: &str) -> toml::Value {
+        toml::from_str::<toml::Value>(&format!("value = {}", value))
+            .ok()
+            .and_then(|table| table.get("value").cloned())
+            .unwrap_or_else(|| toml::Value::String(value.into()))
+    }
+
+    /// Loads the options file, and applies every override on top of it.
+    /// 
+    /// If no options file was given and `Options.toml` doesn't exist, an empty configuration is used.
+    pub fn configuration(&self) -> Result<Configuration, Error> {
+        let mut configuration = match &self.config {
+            Some(config) => Configuration::from_file(config)?,
+            None => Configuration::from_file_or_empty("Options.toml")?,
+        };
+
+        for (key, value) in &self.overrides {
+            configuration.set(key, value.clone());
+        }
+
+        Ok(configuration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, String> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn overrides(args: &[&str]) -> Vec<(String, toml::Value)> {
+        parse(args).unwrap().overrides
+    }
+
+    #[test]
+    fn overrides_are_typed_as_toml() {
+        assert_eq!(overrides(&["times=3", "decay=0.5", "recursive=true", "format=png", "name=\"a b\"", "chunksize=[10, 100]"]), vec![
+            ("times".to_string(), toml::Value::Integer(3)),
+            ("decay".to_string(), toml::Value::Float(0.5)),
+            ("recursive".to_string(), toml::Value::Boolean(true)),
+            ("format".to_string(), toml::Value::String("png".into())),
+            ("name".to_string(), toml::Value::String("a b".into())),
+            ("chunksize".to_string(), toml::Value::Array(vec![10.into(), 100.into()])),
+        ]);
+    }
+
+    #[test]
+    fn overrides_reach_sections() {
+        let path = std::env::temp_dir().join(format!("glitchup-cli-{}.toml", std::process::id()));
+        std::fs::write(&path, "[LoopsConfig]\nloops = 1\nchunksize = 5\n").unwrap();
+
+        let cli = parse(&["-c", &path.to_string_lossy(), "LoopsConfig.loops=[2,5]"]).unwrap();
+        let configuration = cli.configuration();
+        std::fs::remove_file(&path).unwrap();
+        let configuration = configuration.unwrap();
+
+        let loops = configuration.get_option("LoopsConfig", "loops").and_then(|loops| loops.as_list()).unwrap();
+        assert_eq!(loops.iter().map(|value| *value.as_int().unwrap()).collect::<Vec<_>>(), vec![2, 5]);
+        assert_eq!(configuration.get_option("LoopsConfig", "chunksize").and_then(|chunksize| chunksize.as_int()), Some(&5));
+    }
+
+    #[test]
+    fn flags_override_their_options() {
+        let mutations = toml::Value::Array(vec![
+            toml::Value::Array(vec!["Shift".into(), "Swap".into()]),
+            toml::Value::Array(vec!["Loops".into()]),
+        ]);
+
+        assert_eq!(overrides(&["-i", "in.png", "--output", "out.png", "-t", "4", "-s", "42", "-m", "Shift, Swap", "--mutation", "Loops"]), vec![
+            ("inputfile".to_string(), toml::Value::String("in.png".into())),
+            ("outputfile".to_string(), toml::Value::String("out.png".into())),
+            ("times".to_string(), toml::Value::Integer(4)),
+            ("seed".to_string(), toml::Value::Integer(42)),
+            ("mutations".to_string(), mutations),
+        ]);
+        assert_eq!(parse(&["-c", "other.toml", "-h"]).map(|cli| (cli.config, cli.help)), Ok((Some("other.toml".into()), true)));
+    }
+
+    #[test]
+    fn a_lone_dash_streams_unless_given_an_output() {
+        assert_eq!(overrides(&["-"]), vec![
+            ("inputfile".to_string(), toml::Value::String("-".into())),
+            ("outputfile".to_string(), toml::Value::String("-".into())),
+        ]);
+        assert_eq!(overrides(&["-", "-o", "out.jpg"]).len(), 2);
+    }
+
+    #[test]
+    fn malformed_arguments_are_rejected() {
+        for args in &[&["--bogus"][..], &["loops"], &["=5"], &["-x=5"], &["-t"], &["-t", "many"], &["-s", "-1"]] {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+    }
+}
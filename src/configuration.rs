@@ -1,62 +1,381 @@
 use std::fs;
-use cfgmap::{CfgMap};
+use std::path::Path;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use super::mutations::{Mutation, OptionSpec};
+use super::error::Error;
+use super::validation;
+use super::distribution::Distribution;
+use cfgmap::{CfgMap, CfgValue};
 use std::ops::Deref;
 use rayon::prelude::*;
+use rand_core::RngCore;
 
 /* A helper class to represent the bender's configuration */
 
 /// The main configuration of the bender.
 /// 
 /// Represents the entire TOML options file.
+#[derive(Clone)]
 pub struct Configuration {
-    cfg: CfgMap
+    cfg: CfgMap,
+    raw: toml::Value,
+    /// The options file this was loaded from, if any.
+    file: Option<String>,
+    /// The line of every option in the file, such as `chunksize` or `LoopsConfig.loops`.
+    lines: HashMap<String, usize>,
 }
 
 impl Configuration {
 
-    pub fn from_file(config_filename: &str) -> Self {
-        let file = fs::read_to_string(config_filename).expect("Failed to read file into string.");
-        Configuration { cfg: toml::from_str::<toml::Value>(&file).expect("Couldn't parse as toml.").into() }
+    pub fn from_file(config_filename: &str) -> Result<Self, Error> {
+        let file = fs::read_to_string(config_filename).map_err(|err| Error::io(config_filename, err))?;
+        let raw = toml::from_str::<toml::Value>(&file)
+            .map_err(|err| Error::Config(format!("Couldn't parse '{}' as TOML: {}", config_filename, err)))?;
+
+        let mut configuration = Configuration::from_toml(raw);
+        configuration.file = Some(config_filename.into());
+        configuration.lines = locate_keys(&file);
+
+        Ok(configuration)
     }
 
-    /// REDO DOC
-    pub fn verify_config(&self) {
-        let muts_passed : Vec<&String> = self.get_mutations().into_par_iter().flatten().collect();
+    /// Starts building a configuration in code.
+    pub fn builder() -> ConfigurationBuilder {
+        ConfigurationBuilder::new()
+    }
 
-        static POSSIBLE_MUTS : [&str; 11]= ["Void", "Chaos", "Loops", "Reverse", "Shift", "Shuffle", "Swap",
-                                           "Increase", "Gradient", "Multiply", "Compress"];
+    /// Creates a configuration from an already parsed TOML table.
+    pub fn from_toml(raw: toml::Value) -> Self {
+        Configuration { cfg: raw.clone().into(), raw, file: None, lines: HashMap::new() }
+    }
 
-        for string in muts_passed {
-            if !POSSIBLE_MUTS.contains(&string.as_str()) {
-                panic!("Invalid mutation: {:?}\n\tOnly allowed mutations: {:#?}", string, POSSIBLE_MUTS);
-            }
+    /// Creates a configuration from the file, or an empty one if the file doesn't exist.
+    pub fn from_file_or_empty(config_filename: &str) -> Result<Self, Error> {
+        if Path::new(config_filename).exists() {
+            Configuration::from_file(config_filename)
+        } else {
+            Ok(Configuration::from_toml(toml::Value::Table(toml::value::Table::new())))
+        }
+    }
+
+    /// Sets an option, replacing it if it already exists.
+    /// 
+    /// Options under a section can be set using a dot, such as `LoopsConfig.loops`.
+    pub fn set(&mut self, key: &str, value: toml::Value) {
+        let mut path: Vec<&str> = key.split('.').collect();
+        let last = path.pop().unwrap_or_default();
+
+        let mut table = self.raw.as_table_mut();
+        for section in path {
+            table = table.and_then(|table| {
+                let entry = table.entry(section).or_insert_with(|| toml::Value::Table(toml::value::Table::new()));
+                entry.as_table_mut()
+            });
+        }
+
+        if let Some(table) = table {
+            table.insert(last.into(), value);
+        }
+
+        // The option no longer comes from the file.
+        self.lines.remove(key);
+        self.cfg = self.raw.clone().into();
+    }
+
+    /// The options file and line an option was read from, such as `chunksize` or `LoopsConfig.loops`.
+    pub fn locate(&self, key: &str) -> Option<(&str, usize)> {
+        Some((self.file.as_deref()?, *self.lines.get(key)?))
+    }
+
+    /// The whole configuration, as it was parsed.
+    pub fn raw(&self) -> &toml::Value {
+        &self.raw
+    }
+
+    /// Verifies the whole configuration before any work starts.
+    /// 
+    /// Checks that every mutation used exists in the registry, and that every option is valid for the
+    /// mutations reading it, and for the input file. Every problem is reported at once.
+    pub fn verify_config(&self) -> Result<(), Error> {
+        validation::validate(self)
+    }
+
+    /// Fills in the defaults of every option the mutation declares, but that isn't specified.
+    pub fn with_defaults(&self, mutation: &dyn Mutation) -> Cow<'_, Configuration> {
+        let section = format!("{}Config", mutation.name());
+        let missing: Vec<(OptionSpec, toml::Value)> = mutation.options().into_iter()
+            .filter(|option| self.get_option(&section, option.name).is_none())
+            .filter_map(|option| option.default.clone().map(|default| (option, default)))
+            .collect();
+
+        if missing.is_empty() {
+            return Cow::Borrowed(self);
+        }
+
+        let mut configuration = self.clone();
+        for (option, default) in missing {
+            configuration.set(&format!("{}.{}", section, option.name), default);
         }
+
+        Cow::Owned(configuration)
     }
 
-    pub fn get_mutations(&self) -> Vec<Vec<&String>> {
-        self.get("mutations").unwrap().as_list().unwrap()
+    pub fn get_mutations(&self) -> Result<Vec<Vec<&String>>, Error> {
+        let invalid = || Error::Config("Must specify 'mutations' globally as a list of lists of mutations.".into());
+
+        self.get("mutations").and_then(|mutations| mutations.as_list()).ok_or_else(invalid)?
             .into_par_iter()
             .map(|mutation| 
-                mutation.as_list().unwrap().into_par_iter().map(
-                    |s| s.as_str().unwrap()
+                mutation.as_list().ok_or_else(invalid)?.into_par_iter().map(
+                    |s| s.as_str().ok_or_else(invalid)
                 ).collect())
             .collect()
     } 
 
-    pub fn generate_int_from_option(&self, category: &str, value: &str) -> Option<i64> {
-        self.get_option(category, value).and_then(|value| value.generate_int())
+    /// Generates an integer from an option, being either a single value, a `[min, max]` range,
+    /// or a distribution such as `{ dist = "loguniform", min = 1, max = 1_000_000 }`.
+    /// 
+    /// Exactly one number is always drawn from `rng`, so changing an option from a range to a
+    /// single value doesn't change the random numbers used by anything after it.
+    pub fn generate_int_from_option<R: RngCore>(&self, category: &str, value: &str, rng: &mut R) -> Option<i64> {
+        let draw = rng.next_u64();
+        let option = self.get_option(category, value)?;
+
+        if let Some(int) = option.as_int() {
+            return Some(*int);
+        }
+
+        // Values drawn from a distribution are rounded down.
+        if let Some(distribution) = option.as_map() {
+            return Distribution::parse(distribution).ok().map(|distribution| distribution.sample(draw).floor() as i64);
+        }
+
+        match option.as_list()?.as_slice() {
+            [single] => single.as_int().cloned(),
+            [min, max] => {
+                let (min, max) = (*min.as_int()?, *max.as_int()?);
+                if max <= min {
+                    Some(min)
+                } else {
+                    // The width can be larger than `i64::MAX`, but always fits in a `u64`.
+                    Some(min.wrapping_add((draw % max.wrapping_sub(min) as u64) as i64))
+                }
+            },
+            _ => None
+        }
+    }
+
+    /// Generates a float from an option, being either a single value, a `[min, max]` range, or a distribution.
+    /// 
+    /// Integers are accepted as well. Like `generate_int_from_option`, this always draws
+    /// exactly one number from `rng`.
+    pub fn generate_float_from_option<R: RngCore>(&self, category: &str, value: &str, rng: &mut R) -> Option<f64> {
+        let draw = rng.next_u64();
+        let option = self.get_option(category, value)?;
+
+        let as_float = |value: &CfgValue| value.as_float().cloned()
+            .or_else(|| value.as_int().map(|int| *int as f64));
+
+        if let Some(float) = as_float(option) {
+            return Some(float);
+        }
+
+        if let Some(distribution) = option.as_map() {
+            return Distribution::parse(distribution).ok().map(|distribution| distribution.sample(draw));
+        }
+
+        match option.as_list()?.as_slice() {
+            [single] => as_float(single),
+            [min, max] => {
+                let (min, max) = (as_float(min)?, as_float(max)?);
+                Some(min + (max - min) * (draw as f64 / u64::MAX as f64))
+            },
+            _ => None
+        }
+    }
+
+    /// Retrieves the `pool` option, if it was specified: the mutations random chains are drawn from,
+    /// each along with its weight.
+    pub fn get_pool(&self) -> Result<Option<Vec<(&String, u64)>>, Error> {
+        let invalid = || Error::Config(
+            "Must specify 'pool' as a table of mutations and their weights, such as { Shift = 3, Chaos = 1 }.".into());
+
+        let pool = match self.raw.get("pool") {
+            Some(pool) => pool.as_table().ok_or_else(invalid)?,
+            None => return Ok(None),
+        };
+
+        let pool = pool.iter()
+            .map(|(name, weight)| match weight.as_integer() {
+                Some(weight) if weight >= 0 => Ok((name, weight as u64)),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if pool.iter().all(|(_, weight)| *weight == 0) {
+            return Err(Error::Config("Must give at least one mutation in 'pool' a positive weight.".into()));
+        }
+
+        Ok(Some(pool))
+    }
+
+    /// Retrieves the `chain_length` option as an inclusive `(min, max)` range, defaulting to between 1 and 5.
+    pub fn get_chain_length(&self) -> Result<(usize, usize), Error> {
+        let invalid = || Error::Config("Must specify 'chain_length' as a positive integer, or range.".into());
+        let positive = |value: &toml::Value| value.as_integer().filter(|length| *length >= 1).map(|length| length as usize);
+
+        match self.raw.get("chain_length") {
+            None => Ok((1, 5)),
+            Some(toml::Value::Array(range)) => match range.as_slice() {
+                [single] => positive(single).map(|length| (length, length)).ok_or_else(invalid),
+                [min, max] => match (positive(min), positive(max)) {
+                    (Some(min), Some(max)) if min <= max => Ok((min, max)),
+                    _ => Err(invalid()),
+                },
+                _ => Err(invalid()),
+            },
+            Some(length) => positive(length).map(|length| (length, length)).ok_or_else(invalid),
+        }
+    }
+
+    /// Retrieves the `seed` option, if it was specified.
+    pub fn get_seed(&self) -> Result<Option<u64>, Error> {
+        self.get("seed")
+            .map(|seed| seed.as_int()
+                .map(|seed| *seed as u64)
+                .ok_or_else(|| Error::Config("Must specify 'seed' as an integer.".into())))
+            .transpose()
     }
 
-    pub fn generate_float_from_option(&self, category: &str, value: &str) -> Option<f64> {
-        self.get_option(category, value).and_then(|value| value.generate_float())
+    /// Retrieves the `format` option, defaulting to `raw`.
+    pub fn get_format(&self) -> Result<&str, Error> {
+        match self.get("format") {
+            Some(format) => format.as_str()
+                .map(|format| format.as_str())
+                .ok_or_else(|| Error::Config("Must specify 'format' as a string.".into())),
+            None => Ok("raw"),
+        }
     }
 
-    pub fn get_inputfile(&self) -> &str {
+    pub fn get_inputfile(&self) -> Result<&str, Error> {
         self.get("inputfile")
-            .expect("Must specify 'inputfile' option globally.")
+            .ok_or_else(|| Error::Config("Must specify 'inputfile' option globally.".into()))?
             .as_str()
-            .expect("Must specify 'inputfile' as a string.")
+            .map(|inputfile| inputfile.as_str())
+            .ok_or_else(|| Error::Config("Must specify 'inputfile' as a string.".into()))
     }
+
+    /// Retrieves the `inputs` option, if it was specified: the files, directories and globs to bend in a batch.
+    pub fn get_inputs(&self) -> Result<Option<Vec<&str>>, Error> {
+        let invalid = || Error::Config("Must specify 'inputs' as a path or glob, or a list of them.".into());
+
+        let inputs = match self.get("inputs") {
+            Some(inputs) => inputs,
+            None => return Ok(None),
+        };
+
+        match inputs.as_str() {
+            Some(input) => Ok(Some(vec![input.as_str()])),
+            None => inputs.as_list().ok_or_else(invalid)?
+                .iter()
+                .map(|input| input.as_str().map(String::as_str).ok_or_else(invalid))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Some),
+        }
+    }
+}
+
+/// Builds a configuration in code, rather than loading it from a file.
+/// 
+/// Options are set exactly as they would be in the options file. Options under a
+/// section can be set using a dot, such as `LoopsConfig.loops`.
+#[derive(Default)]
+pub struct ConfigurationBuilder {
+    options: Vec<(String, toml::Value)>,
+}
+
+impl ConfigurationBuilder {
+    pub fn new() -> Self {
+        ConfigurationBuilder::default()
+    }
+
+    /// Sets any option, such as `chunksize` or `LoopsConfig.loops`.
+    pub fn option<V: Into<toml::Value>>(mut self, key: &str, value: V) -> Self {
+        self.options.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sets the lists of mutations to use. Each list generates a separate output.
+    pub fn mutations(self, mutations: &[&[&str]]) -> Self {
+        let mutations: Vec<Vec<&str>> = mutations.iter().map(|combo| combo.to_vec()).collect();
+        self.option("mutations", mutations)
+    }
+
+    /// Draws a random chain of mutations for each run instead, from a pool of mutations and their weights.
+    pub fn pool(self, pool: &[(&str, u32)]) -> Self {
+        let pool: toml::value::Table = pool.iter().map(|&(name, weight)| (name.to_string(), weight.into())).collect();
+        self.option("pool", pool)
+    }
+
+    /// Sets how many mutations a chain drawn from `pool` has, between `min` and `max` inclusive.
+    pub fn chain_length(self, min: usize, max: usize) -> Self {
+        self.option("chain_length", vec![min as i64, max as i64])
+    }
+
+    /// Sets the seed to use.
+    pub fn seed(self, seed: u64) -> Self {
+        self.option("seed", seed as i64)
+    }
+
+    /// Sets the file to mutate.
+    pub fn input(self, input: &str) -> Self {
+        self.option("inputfile", input)
+    }
+
+    /// Sets the output file.
+    pub fn output(self, output: &str) -> Self {
+        self.option("outputfile", output)
+    }
+
+    pub fn build(self) -> Configuration {
+        let mut configuration = Configuration::from_toml(toml::Value::Table(toml::value::Table::new()));
+
+        for (key, value) in self.options {
+            configuration.set(&key, value);
+        }
+
+        configuration
+    }
+}
+
+/// Finds the line of every option in a TOML file, keyed like `Configuration::set`.
+fn locate_keys(text: &str) -> HashMap<String, usize> {
+    let is_name = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c));
+
+    let mut lines = HashMap::new();
+    let mut section = String::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+
+        if line.starts_with('[') && !line.starts_with("[[") {
+            // Lines of multi-line lists, such as `["Shift"],`, aren't sections.
+            let name = line[1..].split(']').next().unwrap_or_default().trim();
+            if is_name(name) {
+                section = name.to_string();
+                lines.insert(section.clone(), number + 1);
+            }
+        } else if let Some(equals) = line.find('=') {
+            let key = line[..equals].trim().trim_matches('"');
+            if is_name(key) {
+                let path = if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) };
+                lines.entry(path).or_insert(number + 1);
+            }
+        }
+    }
+
+    lines
 }
 
 impl Deref for Configuration {
@@ -65,4 +384,23 @@ impl Deref for Configuration {
     fn deref(&self) -> &Self::Target {
         &self.cfg
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mutations::MutRng;
+    use rand_core::SeedableRng;
+
+    #[test]
+    fn int_ranges_wider_than_i64_stay_within_bounds() {
+        let (min, max) = (-9_000_000_000_000_000_000i64, 9_000_000_000_000_000_000i64);
+        let config = Configuration::builder().option("chunksize", vec![min, max]).build();
+        let mut rng = MutRng::seed_from_u64(0);
+
+        for _ in 0..1000 {
+            let value = config.generate_int_from_option("SwapConfig", "chunksize", &mut rng).unwrap();
+            assert!(min <= value && value < max);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Distributions that options can draw their values from, beyond uniform `[min, max]` ranges.
+
+use cfgmap::CfgMap;
+
+/// A distribution, written as a table such as `{ dist = "loguniform", min = 1, max = 1_000_000 }`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    /// Every value between `min` and `max` is as likely, exactly like a `[min, max]` range.
+    Uniform { min: f64, max: f64 },
+    /// Every order of magnitude between `min` and `max` is as likely.
+    LogUniform { min: f64, max: f64 },
+    /// Values around `mean`, clamped between `min` and `max` if given.
+    Normal { mean: f64, stddev: f64, min: Option<f64>, max: Option<f64> },
+    /// One of `values`, each picked in proportion to its weight.
+    Choice { values: Vec<f64>, weights: Vec<f64> },
+    /// `min`, plus the number of failures before a success with probability `p`. Capped at `max` if given.
+    Geometric { p: f64, min: f64, max: Option<f64> },
+}
+
+impl Distribution {
+    /// Parses a distribution, returning what was expected if it's invalid.
+    pub fn parse(map: &CfgMap) -> Result<Self, String> {
+        let number = |key: &str| map.get(key)
+            .and_then(|value| value.as_float().cloned().or_else(|| value.as_int().map(|int| *int as f64)));
+
+        let distribution = match map.get("dist").and_then(|dist| dist.as_str()).map(String::as_str) {
+            Some("uniform") => match (number("min"), number("max")) {
+                (Some(min), Some(max)) if min <= max => Distribution::Uniform { min, max },
+                _ => return Err("a uniform distribution with a 'min' no higher than its 'max'".into()),
+            },
+            Some("loguniform") => match (number("min"), number("max")) {
+                (Some(min), Some(max)) if min > 0.0 && min <= max => Distribution::LogUniform { min, max },
+                _ => return Err("a loguniform distribution with a positive 'min' no higher than its 'max'".into()),
+            },
+            Some("normal") => match (number("mean"), number("stddev"), number("min"), number("max")) {
+                (Some(mean), Some(stddev), min, max) if stddev >= 0.0 && min.zip(max).is_none_or(|(min, max)| min <= max) =>
+                    Distribution::Normal { mean, stddev, min, max },
+                _ => return Err("a normal distribution with a 'mean', a non-negative 'stddev', and optionally a 'min' and 'max'".into()),
+            },
+            Some("choice") => {
+                let list = |key: &str| map.get(key).and_then(|list| list.as_list()).map(|list| list.iter()
+                    .map(|value| value.as_float().cloned().or_else(|| value.as_int().map(|int| *int as f64)))
+                    .collect::<Option<Vec<f64>>>());
+
+                let values = list("values").flatten().filter(|values| !values.is_empty());
+                let weights = match list("weights") {
+                    Some(weights) => weights,
+                    None => values.as_ref().map(|values| vec![1.0; values.len()]),
+                };
+
+                match (values, weights) {
+                    (Some(values), Some(weights)) if weights.len() == values.len()
+                        && weights.iter().all(|weight| *weight >= 0.0)
+                        && weights.iter().sum::<f64>() > 0.0 => Distribution::Choice { values, weights },
+                    _ => return Err("a choice distribution with a list of 'values', and optionally as many non-negative 'weights'".into()),
+                }
+            },
+            Some("geometric") => match (number("p"), number("min").unwrap_or(0.0), number("max")) {
+                (Some(p), min, max) if p > 0.0 && p <= 1.0 && max.is_none_or(|max| min <= max) =>
+                    Distribution::Geometric { p, min, max },
+                _ => return Err("a geometric distribution with a 'p' between 0 and 1, and optionally a 'min' and 'max'".into()),
+            },
+            _ => return Err("a distribution with 'dist' set to \"uniform\", \"loguniform\", \"normal\", \"choice\" or \"geometric\"".into()),
+        };
+
+        Ok(distribution)
+    }
+
+    /// Draws a value, using a single number drawn from the RNG.
+    pub fn sample(&self, draw: u64) -> f64 {
+        // A float in [0, 1).
+        let unit = (draw >> 11) as f64 / (1u64 << 53) as f64;
+
+        match self {
+            Distribution::Uniform { min, max } => min + (max - min) * unit,
+            Distribution::LogUniform { min, max } => (min.ln() + (max.ln() - min.ln()) * unit).exp().max(*min).min(*max),
+            Distribution::Normal { mean, stddev, min, max } => {
+                // Box-Muller, using each half of the draw as a separate uniform number.
+                let first = ((draw >> 32) as f64 + 1.0) / (u32::MAX as f64 + 2.0);
+                let second = (draw & 0xFFFF_FFFF) as f64 / (u32::MAX as f64 + 1.0);
+                let normal = (-2.0 * first.ln()).sqrt() * (2.0 * std::f64::consts::PI * second).cos();
+
+                clamp(mean + stddev * normal, *min, *max)
+            },
+            Distribution::Choice { values, weights } => {
+                let mut target = unit * weights.iter().sum::<f64>();
+                for (value, weight) in values.iter().zip(weights) {
+                    if target < *weight {
+                        return *value;
+                    }
+                    target -= weight;
+                }
+                values[values.len() - 1]
+            },
+            Distribution::Geometric { p, min, max } => {
+                let failures = if *p >= 1.0 { 0.0 } else { ((1.0 - unit).ln() / (1.0 - p).ln()).floor() };
+                clamp(min + failures, None, *max)
+            },
+        }
+    }
+
+    /// The lowest value that can be drawn, if there is one.
+    pub fn lowest(&self) -> Option<f64> {
+        match self {
+            Distribution::Uniform { min, .. } | Distribution::LogUniform { min, .. } => Some(*min),
+            Distribution::Normal { min, .. } => *min,
+            Distribution::Choice { values, .. } => values.iter().cloned().reduce(f64::min),
+            Distribution::Geometric { min, .. } => Some(*min),
+        }
+    }
+}
+
+fn clamp(value: f64, min: Option<f64>, max: Option<f64>) -> f64 {
+    let value = min.map_or(value, |min| value.max(min));
+    max.map_or(value, |max| value.min(max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Configuration;
+    use crate::mutations::MutRng;
+    use rand_core::{RngCore, SeedableRng};
+
+    fn parse(text: &str) -> Result<Distribution, String> {
+        Distribution::parse(&toml::from_str::<toml::Value>(text).unwrap().into())
+    }
+
+    /// Samples the distribution with many draws, spread evenly across every `u64`.
+    fn samples(text: &str) -> Vec<f64> {
+        let distribution = parse(text).unwrap();
+        let mut rng = MutRng::seed_from_u64(0);
+        let mut draws: Vec<u64> = (0..10_000).map(|_| rng.next_u64()).collect();
+        draws.extend(&[0, 1, u64::MAX, u64::MAX - 1, 1 << 63]);
+
+        draws.into_iter().map(|draw| distribution.sample(draw)).collect()
+    }
+
+    #[test]
+    fn samples_stay_within_their_support() {
+        assert!(samples("dist = 'loguniform'\nmin = 1\nmax = 1_000_000").iter().all(|&value| (1.0..=1_000_000.0).contains(&value)));
+        assert!(samples("dist = 'normal'\nmean = 0\nstddev = 100\nmin = -50\nmax = 10").iter().all(|&value| (-50.0..=10.0).contains(&value)));
+        assert!(samples("dist = 'normal'\nmean = 5\nstddev = 0").iter().all(|&value| value == 5.0));
+        assert!(samples("dist = 'geometric'\np = 0.2\nmin = 3\nmax = 40").iter().all(|&value| (3.0..=40.0).contains(&value) && value.fract() == 0.0));
+        assert!(samples("dist = 'geometric'\np = 1").iter().all(|&value| value == 0.0));
+        assert!(samples("dist = 'choice'\nvalues = [2, 4.5, 8]").iter().all(|value| [2.0, 4.5, 8.0].contains(value)));
+        assert!(samples("dist = 'uniform'\nmin = -1\nmax = 1").iter().all(|&value| (-1.0..=1.0).contains(&value)));
+    }
+
+    #[test]
+    fn normal_samples_center_on_the_mean() {
+        let samples = samples("dist = 'normal'\nmean = 10\nstddev = 2");
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+
+        assert!((mean - 10.0).abs() < 0.1, "{}", mean);
+    }
+
+    #[test]
+    fn choice_respects_its_weights() {
+        let samples = samples("dist = 'choice'\nvalues = [1, 2, 3]\nweights = [1, 0, 3]");
+        let count = |value: f64| samples.iter().filter(|&&sample| sample == value).count() as f64 / samples.len() as f64;
+
+        assert_eq!(count(2.0), 0.0);
+        assert!((count(1.0) - 0.25).abs() < 0.02, "{}", count(1.0));
+        assert!((count(3.0) - 0.75).abs() < 0.02, "{}", count(3.0));
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        for text in &[
+            "dist = 'loguniform'\nmin = 0\nmax = 10",
+            "dist = 'loguniform'\nmin = -1\nmax = 10",
+            "dist = 'loguniform'\nmin = 10\nmax = 1",
+            "dist = 'normal'\nmean = 0\nstddev = -1",
+            "dist = 'normal'\nmean = 0\nstddev = 1\nmin = 2\nmax = 1",
+            "dist = 'choice'\nvalues = []",
+            "dist = 'choice'\nvalues = [1, 2]\nweights = [1]",
+            "dist = 'choice'\nvalues = [1, 2]\nweights = [0, 0]",
+            "dist = 'geometric'\np = 0",
+            "dist = 'geometric'\np = 1.5",
+            "dist = 'geometric'\np = -0.5",
+            "dist = 'gamma'\nshape = 2",
+        ] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn options_draw_exactly_once() {
+        let config = Configuration::builder()
+            .option("single", 5)
+            .option("range", vec![1, 100])
+            .option("loguniform", toml::from_str::<toml::Value>("dist = 'loguniform'\nmin = 1\nmax = 100").unwrap())
+            .option("normal", toml::from_str::<toml::Value>("dist = 'normal'\nmean = 0\nstddev = 1").unwrap())
+            .option("choice", toml::from_str::<toml::Value>("dist = 'choice'\nvalues = [1, 2]").unwrap())
+            .option("geometric", toml::from_str::<toml::Value>("dist = 'geometric'\np = 0.5").unwrap())
+            .option("invalid", toml::from_str::<toml::Value>("dist = 'geometric'\np = 5").unwrap())
+            .build();
+
+        for option in &["single", "range", "loguniform", "normal", "choice", "geometric", "invalid", "missing"] {
+            // The number drawn right after the option.
+            let mut next = MutRng::seed_from_u64(7);
+            next.next_u64();
+            let next = next.next_u64();
+
+            let mut rng = MutRng::seed_from_u64(7);
+            config.generate_int_from_option("ShiftConfig", option, &mut rng);
+            assert_eq!(rng.next_u64(), next, "{}", option);
+
+            let mut rng = MutRng::seed_from_u64(7);
+            config.generate_float_from_option("ShiftConfig", option, &mut rng);
+            assert_eq!(rng.next_u64(), next, "{}", option);
+        }
+    }
+}
+
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while loading the configuration or bending a file.
+#[derive(Debug)]
+pub enum Error {
+    /// The configuration is invalid, such as an unknown mutation or a missing `inputfile`.
+    Config(String),
+    /// A file couldn't be read or written.
+    Io { path: String, error: io::Error },
+    /// An option is missing, or its value can't be used.
+    InvalidOption { section: String, option: String, expected: String },
+    /// The data is too small for the mutation to work with the options given.
+    DataTooSmall { mutation: String, len: usize, needed: usize },
+    /// The data couldn't be parsed as, or written back to, its format.
+    Format { format: String, message: String },
+    /// An error caused by the option at a line of the options file.
+    At { file: String, line: usize, error: Box<Error> },
+    /// Several errors, all found at once.
+    Several(Vec<Error>),
+}
+
+impl Error {
+    pub fn io(path: &str, error: io::Error) -> Self {
+        Error::Io { path: path.into(), error }
+    }
+
+    /// An option under `section` (or set globally) that is missing, or isn't `expected`.
+    pub fn invalid_option<S: Into<String>>(section: &str, option: &str, expected: S) -> Self {
+        Error::InvalidOption { section: section.into(), option: option.into(), expected: expected.into() }
+    }
+
+    pub fn format<S: Into<String>>(format: &str, message: S) -> Self {
+        Error::Format { format: format.into(), message: message.into() }
+    }
+
+    /// Points an error at the line of the options file it comes from.
+    pub fn at(self, file: &str, line: usize) -> Self {
+        Error::At { file: file.into(), line, error: Box::new(self) }
+    }
+
+    /// The exit code of the executable when this error occurs, so scripts can tell what failed.
+    /// 
+    /// Several errors exit with the code of the first one.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Config(_) => 2,
+            Error::Io { .. } => 3,
+            Error::InvalidOption { .. } => 4,
+            Error::DataTooSmall { .. } => 5,
+            Error::Format { .. } => 6,
+            Error::At { error, .. } => error.exit_code(),
+            Error::Several(errors) => errors.first().map_or(2, Error::exit_code),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Config(message) => write!(f, "{}", message),
+            Error::Io { path, error } => write!(f, "Couldn't access '{}': {}", path, error),
+            Error::InvalidOption { section, option, expected } =>
+                write!(f, "Expected '{}' to be under '{}' or globally as {}.", option, section, expected),
+            Error::DataTooSmall { mutation, len, needed } =>
+                write!(f, "Cannot perform {} - it needs at least {} bytes, but only has {}.", mutation, needed, len),
+            Error::Format { format, message } => write!(f, "Couldn't process the file as {}: {}", format, message),
+            Error::At { file, line, error } => write!(f, "{}:{}: {}", file, line, error),
+            Error::Several(errors) => {
+                let lines : Vec<String> = errors.iter().map(Error::to_string).collect();
+                write!(f, "{}", lines.join("\n"))
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            Error::At { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use super::{Format, Decoded, Extracted};
+use super::{audio, iff};
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use std::convert::TryInto;
+use std::ops::Range;
+
+/// AIFF and AIFF-C audio.
+/// 
+/// Mutates only the samples in the `SSND` chunk, leaving every other chunk intact.
+/// Options are read from `[AiffConfig]`:
+/// 
+/// * `sample_aligned` - Whether `Reverse`, `Shift`, `Swap` and `Loops` work on whole frames,
+///   rather than splitting samples. Defaults to `true`.
+pub struct Aiff;
+
+fn read_u16(data: &[u8], pos: usize) -> Option<usize> {
+    data.get(pos..pos + 2).map(|bytes| u16::from_be_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<usize> {
+    data.get(pos..pos + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+impl Format for Aiff {
+    fn name(&self) -> &str {
+        "aiff"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(b"FORM") && (data.get(8..12) == Some(b"AIFF") || data.get(8..12) == Some(b"AIFC"))
+    }
+
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error> {
+        if !self.detect(data) {
+            return Err(Error::format("aiff", "Not an AIFF file."));
+        }
+
+        let chunks = iff::chunks(data, 12..data.len(), true);
+
+        let comm = chunks.iter().find(|chunk| chunk.id == b"COMM")
+            .ok_or_else(|| Error::format("aiff", "AIFF has no 'COMM' chunk."))?;
+        let ssnd = chunks.iter().find(|chunk| chunk.id == b"SSND")
+            .ok_or_else(|| Error::format("aiff", "AIFF has no 'SSND' chunk."))?;
+
+        let truncated = || Error::format("aiff", "AIFF is truncated.");
+        let channels = read_u16(data, comm.body.start).ok_or_else(truncated)?;
+        let sample_size = read_u16(data, comm.body.start + 6).ok_or_else(truncated)?;
+
+        // The samples start after the offset and block size, skipping `offset` more bytes.
+        let offset = read_u32(data, ssnd.body.start).ok_or_else(truncated)?;
+        let start = (ssnd.body.start + 8).saturating_add(offset).min(ssnd.body.end);
+
+        let frame = channels * sample_size.div_ceil(8);
+        let alignment = audio::alignment(cfg, "AiffConfig", frame)?;
+
+        Ok(Box::new(Extracted::new(data, vec![Range { start, end: ssnd.body.end }]).aligned(alignment)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 24-bit stereo AIFF, with an odd-sized chunk before the `SSND` chunk skipping `offset` bytes.
+    fn aiff(offset: u32, size: u32) -> Vec<u8> {
+        let mut out = b"FORM\0\0\0\0AIFF".to_vec();
+
+        out.extend_from_slice(b"COMM");
+        out.extend_from_slice(&18u32.to_be_bytes());
+        out.extend_from_slice(&2u16.to_be_bytes());
+        out.extend_from_slice(&2u32.to_be_bytes());
+        out.extend_from_slice(&24u16.to_be_bytes());
+        out.extend_from_slice(&[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+
+        // Padded to an even length.
+        out.extend_from_slice(b"NAME");
+        out.extend_from_slice(&5u32.to_be_bytes());
+        out.extend_from_slice(b"glitc\0");
+
+        out.extend_from_slice(b"SSND");
+        out.extend_from_slice(&size.to_be_bytes());
+        out.extend_from_slice(&offset.to_be_bytes());
+        out.extend_from_slice(&0u32.to_be_bytes());
+        out.extend(1..=12);
+
+        let form = (out.len() - 8) as u32;
+        out[4..8].copy_from_slice(&form.to_be_bytes());
+        out
+    }
+
+    #[test]
+    fn samples_start_after_the_offset() {
+        let decoded = Aiff.decode(&aiff(0, 20), &Configuration::builder().build()).unwrap();
+        assert_eq!(decoded.data(), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(decoded.alignment(), 6);
+
+        let decoded = Aiff.decode(&aiff(6, 20), &Configuration::builder().build()).unwrap();
+        assert_eq!(decoded.data(), &[7, 8, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn truncated_samples_are_clipped_to_the_file() {
+        let decoded = Aiff.decode(&aiff(0, 1000), &Configuration::builder().build()).unwrap();
+        assert_eq!(decoded.data(), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+
+        // An offset past the end leaves nothing to mutate.
+        let decoded = Aiff.decode(&aiff(u32::MAX, 20), &Configuration::builder().build()).unwrap();
+        assert!(decoded.data().is_empty());
+    }
+
+    #[test]
+    fn frames_are_kept_together_unless_disabled() {
+        let config = Configuration::builder().option("AiffConfig.sample_aligned", false).build();
+
+        assert_eq!(Aiff.decode(&aiff(0, 20), &config).unwrap().alignment(), 1);
+    }
+
+    #[test]
+    fn files_without_samples_are_rejected() {
+        // Ends right after the `COMM` chunk.
+        assert!(Aiff.decode(&aiff(0, 20)[..38], &Configuration::builder().build()).is_err());
+    }
+}
@@ -0,0 +1,14 @@
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+/// The number of bytes mutations should keep together, based on the `sample_aligned` option.
+/// 
+/// Frames (one sample for each channel) are kept together by default.
+pub fn alignment(cfg: &Configuration, category: &str, frame: usize) -> Result<usize, Error> {
+    let aligned = match cfg.get_option(category, "sample_aligned") {
+        Some(aligned) => *aligned.as_bool().ok_or_else(|| Error::invalid_option(category, "sample_aligned", "a boolean"))?,
+        None => true,
+    };
+
+    Ok(if aligned { frame.max(1) } else { 1 })
+}
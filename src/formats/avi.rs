@@ -0,0 +1,389 @@
+use super::{Format, Decoded, Extracted};
+use super::iff::{self, Chunk};
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ops::Range;
+
+/// AVI video.
+/// 
+/// Mutates only the frames of the video, leaving the headers, index and audio intact.
+/// Options are read from `[AviConfig]`:
+/// 
+/// * `target` - Which frames to mutate: `all` (default), `key` or `delta`.
+/// 
+/// Restructuring the video itself, such as removing keyframes, is done by mutations
+/// like `DropKeyframes` and `Bloom` on the raw file instead.
+pub struct Avi;
+
+/// Marks an index entry as a keyframe.
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+/// A chunk stored in the `movi` list, such as a frame of video or a block of audio.
+#[derive(Clone)]
+pub struct AviChunk {
+    /// The stream and type of the chunk, such as `00dc` for compressed video.
+    pub id: [u8; 4],
+    pub data: Vec<u8>,
+    /// Whether the frame can be decoded on its own. Always true for anything but video.
+    pub keyframe: bool,
+    /// Where the chunk was in the file it was read from.
+    pub offset: usize,
+}
+
+impl AviChunk {
+    /// Whether the chunk is a frame of video.
+    pub fn is_video(&self) -> bool {
+        &self.id[2..] == b"dc" || &self.id[2..] == b"db"
+    }
+}
+
+/// An AVI, split into its headers and the chunks of its `movi` list.
+/// 
+/// Only the AVI 1.0 index (`idx1`) is written back. OpenDML indices are disabled,
+/// so that players fall back to it.
+pub struct AviFile {
+    /// Every chunk before `movi`, such as the headers.
+    head: Vec<u8>,
+    pub chunks: Vec<AviChunk>,
+    /// Every chunk after `movi`, apart from the index.
+    tail: Vec<u8>,
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn write_u32(data: &mut [u8], pos: usize, value: u32) {
+    if let Some(bytes) = data.get_mut(pos..pos + 4) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Whether an MPEG-4 Part 2 frame is an I-VOP, or `None` if it doesn't contain a VOP.
+fn mpeg4_keyframe(data: &[u8]) -> Option<bool> {
+    data.windows(4)
+        .position(|window| window == [0, 0, 1, 0xB6])
+        .and_then(|pos| data.get(pos + 4))
+        .map(|byte| byte >> 6 == 0)
+}
+
+/// The body of a chunk in the `movi` list, its id, and whether it's a keyframe.
+type MoviEntry = (Range<usize>, [u8; 4], bool);
+
+/// Reads every chunk of the `movi` list.
+fn movi_chunks(data: &[u8]) -> Result<Vec<MoviEntry>, Error> {
+    if !data.starts_with(b"RIFF") || data.get(8..12) != Some(b"AVI ") {
+        return Err(Error::format("avi", "Not an AVI file."));
+    }
+
+    let top = iff::chunks(data, 12..data.len(), false);
+
+    if top.iter().any(|chunk| chunk.is_list(data, b"AVIX")) {
+        return Err(Error::format("avi", "OpenDML AVIs (larger than 1GB) aren't supported."));
+    }
+
+    let movi = top.iter().find(|chunk| chunk.is_list(data, b"movi"))
+        .ok_or_else(|| Error::format("avi", "AVI has no 'movi' list."))?;
+
+    // Lists of chunks ('rec ') are flattened, while old indices and padding are left out.
+    fn flatten<'a>(data: &'a [u8], chunks: Vec<Chunk<'a>>, out: &mut Vec<Chunk<'a>>) {
+        for chunk in chunks {
+            if chunk.id == b"LIST" {
+                flatten(data, iff::chunks(data, chunk.body.start + 4..chunk.body.end, false), out);
+            } else if chunk.id != b"JUNK" && !chunk.id.starts_with(b"ix") {
+                out.push(chunk);
+            }
+        }
+    }
+
+    let mut chunks = Vec::new();
+    flatten(data, iff::chunks(data, movi.body.start + 4..movi.body.end, false), &mut chunks);
+
+    // The index flags keyframes. Its entries are matched to chunks by offset and id rather than by order,
+    // since it can list chunks left out above, such as 'rec ' lists.
+    let base = movi.body.start;
+    let flags: HashMap<(usize, &[u8]), u32> = top.iter()
+        .find(|chunk| chunk.id == b"idx1")
+        .map(|idx1| data[idx1.body.clone()].chunks_exact(16).filter_map(|entry| {
+            let (id, offset) = (&entry[..4], read_u32(entry, 8).unwrap() as usize);
+
+            // Offsets are relative to the 'movi' list, though some muxers count them from the start of the file.
+            [base.checked_add(offset), Some(offset)].iter()
+                .flatten()
+                .find(|&&pos| data.get(pos..pos + 4) == Some(id))
+                .map(|&pos| ((pos, id), read_u32(entry, 4).unwrap()))
+        }).collect())
+        .unwrap_or_default();
+
+    Ok(chunks.iter().map(|chunk| {
+        let id: [u8; 4] = chunk.id.try_into().unwrap();
+        let body = &data[chunk.body.clone()];
+        let video = &id[2..] == b"dc" || &id[2..] == b"db";
+
+        // MPEG-4 Part 2 frames state their own type, which is more reliable than the index.
+        let keyframe = !video || mpeg4_keyframe(body)
+            .or_else(|| flags.get(&(chunk.body.start - 8, chunk.id)).map(|flags| flags & AVIIF_KEYFRAME != 0))
+            .unwrap_or(true);
+
+        (chunk.body.clone(), id, keyframe)
+    }).collect())
+}
+
+impl AviFile {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let chunks = movi_chunks(data)?;
+        let top = iff::chunks(data, 12..data.len(), false);
+        let movi = top.iter().position(|chunk| chunk.is_list(data, b"movi")).unwrap();
+
+        let head = top[..movi].iter().flat_map(|chunk| data[chunk.bounds(data)].to_vec()).collect();
+        let tail = top[movi + 1..].iter()
+            .filter(|chunk| chunk.id != b"idx1")
+            .flat_map(|chunk| data[chunk.bounds(data)].to_vec())
+            .collect();
+
+        Ok(AviFile {
+            head,
+            chunks: chunks.into_iter()
+                .map(|(body, id, keyframe)| AviChunk { id, offset: body.start - 8, data: data[body].to_vec(), keyframe })
+                .collect(),
+            tail,
+        })
+    }
+
+    /// The format of the video stream (its `strf` chunk), used to check whether two clips are compatible.
+    pub fn video_format(&self) -> Option<&[u8]> {
+        let head = &self.head;
+        let hdrl = iff::chunks(head, 0..head.len(), false).into_iter().find(|chunk| chunk.is_list(head, b"hdrl"))?;
+
+        iff::chunks(head, hdrl.body.start + 4..hdrl.body.end, false).into_iter()
+            .filter(|chunk| chunk.is_list(head, b"strl"))
+            .map(|strl| iff::chunks(head, strl.body.start + 4..strl.body.end, false))
+            .find(|strl| strl.iter().any(|chunk| chunk.id == b"strh" && head.get(chunk.body.start..chunk.body.start + 4) == Some(b"vids")))
+            .and_then(|strl| strl.into_iter().find(|chunk| chunk.id == b"strf"))
+            .map(|strf| &head[strf.body])
+    }
+
+    /// Updates the frame counts of the headers, and disables OpenDML indices, which no longer match.
+    fn patch_head(&self) -> Vec<u8> {
+        let mut head = self.head.clone();
+        let frames = self.chunks.iter().filter(|chunk| chunk.is_video()).count() as u32;
+
+        let mut patches = Vec::new();
+        {
+            let head = &self.head;
+            let hdrl = iff::chunks(head, 0..head.len(), false).into_iter().find(|chunk| chunk.is_list(head, b"hdrl"));
+
+            for chunk in hdrl.map(|hdrl| iff::chunks(head, hdrl.body.start + 4..hdrl.body.end, false)).unwrap_or_default() {
+                if chunk.id == b"avih" {
+                    patches.push((chunk.body.start + 16, Some(frames)));
+                }
+
+                if chunk.is_list(head, b"strl") {
+                    for chunk in iff::chunks(head, chunk.body.start + 4..chunk.body.end, false) {
+                        if chunk.id == b"strh" && head.get(chunk.body.start..chunk.body.start + 4) == Some(b"vids") {
+                            patches.push((chunk.body.start + 32, Some(frames)));
+                        } else if chunk.id == b"indx" {
+                            patches.push((chunk.body.start - 8, None));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (pos, value) in patches {
+            match value {
+                Some(value) => write_u32(&mut head, pos, value),
+                None => head[pos..pos + 4].copy_from_slice(b"JUNK"),
+            }
+        }
+
+        head
+    }
+
+    /// Writes the file back, along with a new index.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut movi = b"movi".to_vec();
+        let mut index = Vec::new();
+
+        for chunk in &self.chunks {
+            // Offsets are relative to the start of the 'movi' list's data.
+            let flags = if chunk.keyframe { AVIIF_KEYFRAME } else { 0 };
+            index.extend_from_slice(&chunk.id);
+            for value in &[flags, movi.len() as u32, chunk.data.len() as u32] {
+                index.extend_from_slice(&value.to_le_bytes());
+            }
+
+            movi.extend_from_slice(&chunk.id);
+            movi.extend_from_slice(&(chunk.data.len() as u32).to_le_bytes());
+            movi.extend_from_slice(&chunk.data);
+            if chunk.data.len() % 2 == 1 {
+                movi.push(0);
+            }
+        }
+
+        let mut out = b"RIFF\0\0\0\0AVI ".to_vec();
+        out.extend_from_slice(&self.patch_head());
+        out.extend_from_slice(b"LIST");
+        out.extend_from_slice(&(movi.len() as u32).to_le_bytes());
+        out.extend_from_slice(&movi);
+        out.extend_from_slice(b"idx1");
+        out.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        out.extend_from_slice(&index);
+        out.extend_from_slice(&self.tail);
+
+        let size = out.len() as u32 - 8;
+        write_u32(&mut out, 4, size);
+        out
+    }
+}
+
+impl Format for Avi {
+    fn name(&self) -> &str {
+        "avi"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(b"RIFF") && data.get(8..12) == Some(b"AVI ")
+    }
+
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error> {
+        let target = cfg.get_option("AviConfig", "target")
+            .and_then(|target| target.as_str())
+            .map_or("all", |target| target.as_str());
+
+        if !["all", "key", "delta"].contains(&target) {
+            return Err(Error::invalid_option("AviConfig", "target",
+                format!("'all', 'key' or 'delta', not '{}'", target)));
+        }
+
+        let frames = movi_chunks(data)?.into_iter()
+            .filter(|(_, id, keyframe)| {
+                let video = &id[2..] == b"dc" || &id[2..] == b"db";
+                video && match target {
+                    "key" => *keyframe,
+                    "delta" => !*keyframe,
+                    _ => true,
+                }
+            })
+            .map(|(body, _, _)| body)
+            .collect();
+
+        Ok(Box::new(Extracted::new(data, frames)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+
+    fn list(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        chunk(b"LIST", &[kind, body].concat())
+    }
+
+    /// An AVI with a keyframe, a `rec ` list holding audio and a delta frame, then another keyframe.
+    /// 
+    /// Its index lists the `rec ` list as well, as many muxers do. Offsets are counted from the start of the file
+    /// if `absolute` is set.
+    fn avi(absolute: bool) -> Vec<u8> {
+        let mut strh = b"vids".to_vec();
+        strh.resize(56, 0);
+        let strl = list(b"strl", &[chunk(b"strh", &strh), chunk(b"strf", &[7; 40])].concat());
+        let hdrl = list(b"hdrl", &[chunk(b"avih", &[0; 56]), strl].concat());
+
+        let mut movi = b"movi".to_vec();
+        let mut index = Vec::new();
+        let mut entry = |id: &[u8], flags: u32, offset: usize| {
+            index.extend_from_slice(id);
+            index.extend_from_slice(&flags.to_le_bytes());
+            index.extend_from_slice(&(offset as u32).to_le_bytes());
+            index.extend_from_slice(&0u32.to_le_bytes());
+        };
+
+        movi.extend(chunk(b"JUNK", &[0; 6]));
+        entry(b"00dc", AVIIF_KEYFRAME, movi.len());
+        movi.extend(chunk(b"00dc", b"key"));
+
+        let rec = movi.len();
+        let audio = chunk(b"01wb", b"audio!");
+        let delta = chunk(b"00dc", b"delta");
+        entry(b"LIST", 0, rec);
+        entry(b"01wb", AVIIF_KEYFRAME, rec + 12);
+        entry(b"00dc", 0, rec + 12 + audio.len());
+        movi.extend(list(b"rec ", &[audio, delta].concat()));
+
+        entry(b"00dc", AVIIF_KEYFRAME, movi.len());
+        movi.extend(chunk(b"00dc", b"key again"));
+
+        let mut out = b"RIFF\0\0\0\0AVI ".to_vec();
+        out.extend(&hdrl);
+
+        if absolute {
+            let base = out.len() + 8;
+            for offset in index.chunks_exact_mut(16).map(|entry| &mut entry[8..12]) {
+                let value = u32::from_le_bytes((&*offset).try_into().unwrap()) + base as u32;
+                offset.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        out.extend(chunk(b"LIST", &movi));
+        out.extend(chunk(b"idx1", &index));
+        out
+    }
+
+    #[test]
+    fn keyframes_are_matched_by_offset() {
+        for &absolute in &[false, true] {
+            let file = AviFile::parse(&avi(absolute)).unwrap();
+
+            let chunks: Vec<(&[u8], &[u8], bool)> = file.chunks.iter()
+                .map(|chunk| (&chunk.id[..], &chunk.data[..], chunk.keyframe))
+                .collect();
+
+            assert_eq!(chunks, vec![
+                (&b"00dc"[..], &b"key"[..], true),
+                (&b"01wb"[..], &b"audio!"[..], true),
+                (&b"00dc"[..], &b"delta"[..], false),
+                (&b"00dc"[..], &b"key again"[..], true),
+            ]);
+        }
+    }
+
+    #[test]
+    fn written_file_parses_again() {
+        let mut file = AviFile::parse(&avi(false)).unwrap();
+        file.chunks.remove(0);
+
+        let bytes = file.to_bytes();
+        assert_eq!(read_u32(&bytes, 4), Some(bytes.len() as u32 - 8));
+
+        let parsed = AviFile::parse(&bytes).unwrap();
+        let chunks: Vec<(&[u8], bool)> = parsed.chunks.iter().map(|chunk| (&chunk.data[..], chunk.keyframe)).collect();
+        assert_eq!(chunks, vec![(&b"audio!"[..], true), (&b"delta"[..], false), (&b"key again"[..], true)]);
+
+        // Both the main header and the video stream header count the frames left.
+        let avih = 12 + 12;
+        assert_eq!(read_u32(&bytes, avih + 8 + 16), Some(2));
+        assert_eq!(parsed.video_format(), Some(&[7; 40][..]));
+    }
+
+    #[test]
+    fn mpeg4_frames_state_their_type() {
+        assert_eq!(mpeg4_keyframe(&[0, 0, 1, 0xB6, 0x00]), Some(true));
+        assert_eq!(mpeg4_keyframe(&[9, 0, 0, 1, 0xB6, 0x40]), Some(false));
+        assert_eq!(mpeg4_keyframe(b"no vop"), None);
+    }
+}
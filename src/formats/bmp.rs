@@ -0,0 +1,160 @@
+use super::{Format, Decoded, Extracted, Pixels};
+use super::pixels::{self, PixelLayout};
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use std::convert::TryInto;
+
+/// BMP images.
+/// 
+/// Mutates only the pixel array, leaving the headers, palette and row padding intact.
+/// Options are read from `[BmpConfig]`:
+/// 
+/// * `region` - Only mutates the pixels within `[x, y, width, height]`, counted from the top left.
+pub struct Bmp;
+
+fn read_u16(data: &[u8], pos: usize) -> Option<usize> {
+    data.get(pos..pos + 2).map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<usize> {
+    data.get(pos..pos + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+fn read_i32(data: &[u8], pos: usize) -> Option<i64> {
+    data.get(pos..pos + 4).map(|bytes| i32::from_le_bytes(bytes.try_into().unwrap()) as i64)
+}
+
+/// Reads the headers of a BMP to find where each row of pixels is.
+fn parse_layout(data: &[u8]) -> Result<PixelLayout, Error> {
+    let truncated = || Error::format("bmp", "BMP is truncated.");
+
+    let offset = read_u32(data, 10).ok_or_else(truncated)?;
+    let header_size = read_i32(data, 14).ok_or_else(truncated)?;
+
+    // The old OS/2 header uses 16-bit dimensions.
+    let (width, height, bits_per_pixel, compression) = if header_size == 12 {
+        (read_u16(data, 18).ok_or_else(truncated)? as i64,
+         read_u16(data, 20).ok_or_else(truncated)? as i64,
+         read_u16(data, 24).ok_or_else(truncated)?,
+         0)
+    } else {
+        (read_i32(data, 18).ok_or_else(truncated)?,
+         read_i32(data, 22).ok_or_else(truncated)?,
+         read_u16(data, 28).ok_or_else(truncated)?,
+         read_i32(data, 30).ok_or_else(truncated)?)
+    };
+
+    // Only uncompressed pixels (BI_RGB and BI_BITFIELDS) are laid out in rows.
+    if compression != 0 && compression != 3 && compression != 6 {
+        return Err(Error::format("bmp", format!("Compressed BMPs aren't supported (compression {}).", compression)));
+    }
+
+    let mut layout = PixelLayout {
+        width: width.unsigned_abs() as usize,
+        height: height.unsigned_abs() as usize,
+        bits_per_pixel,
+        rows: Vec::new(),
+    };
+
+    // Rows are padded to 4 bytes, and stored bottom-up unless the height is negative.
+    let row_len = layout.row_len().ok_or_else(truncated)?;
+    let stride = row_len.checked_add(3).ok_or_else(truncated)? / 4 * 4;
+
+    // Empty rows would all fit in the file, however many the header claims.
+    if row_len == 0 || layout.height == 0 {
+        return Err(Error::format("bmp", "BMP has no pixels."));
+    }
+
+    // The row stored last ends furthest into the file, whichever order rows are in.
+    let end = stride.checked_mul(layout.height - 1)
+        .and_then(|last| last.checked_add(offset))
+        .and_then(|last| last.checked_add(row_len));
+
+    if end.is_none_or(|end| end > data.len()) {
+        return Err(truncated());
+    }
+
+    layout.rows = (0..layout.height)
+        .map(|y| if height > 0 { layout.height - 1 - y } else { y })
+        .map(|row| offset + row * stride)
+        .collect();
+
+    Ok(layout)
+}
+
+impl Format for Bmp {
+    fn name(&self) -> &str {
+        "bmp"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(b"BM")
+    }
+
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error> {
+        if !self.detect(data) {
+            return Err(Error::format("bmp", "Not a BMP file."));
+        }
+
+        let layout = parse_layout(data)?;
+        let region = pixels::get_region(cfg, "BmpConfig")?;
+        let ranges = layout.ranges(region);
+
+        // Pixels are stored as BGR, while smaller depths use a palette.
+        let order = match layout.bits_per_pixel {
+            24 => Some("bgr"),
+            32 => Some("bgra"),
+            _ => None,
+        };
+        let pixels = order.map(|order| Pixels::new(ranges.first().map_or(0, |row| row.len()), order));
+
+        Ok(Box::new(Extracted::new(data, ranges).with_pixels(pixels)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 24-bit BMP, whose pixels start at `offset`.
+    fn bmp(offset: u32, width: i32, height: i32) -> Vec<u8> {
+        let mut out = b"BM".to_vec();
+        out.extend_from_slice(&70u32.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&40u32.to_le_bytes());
+        out.extend_from_slice(&width.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&24u16.to_le_bytes());
+        out.extend_from_slice(&[0; 24]);
+        out.extend((0..16).map(|byte| byte as u8));
+        out
+    }
+
+    #[test]
+    fn rows_are_stored_bottom_up() {
+        let layout = parse_layout(&bmp(54, 2, 2)).unwrap();
+        assert_eq!(layout.rows, vec![62, 54]);
+
+        let layout = parse_layout(&bmp(54, 2, -2)).unwrap();
+        assert_eq!(layout.rows, vec![54, 62]);
+    }
+
+    #[test]
+    fn offsets_outside_the_file_are_rejected() {
+        assert!(parse_layout(&bmp(u32::MAX, 2, 2)).is_err());
+        assert!(parse_layout(&bmp(60, 2, 2)).is_err());
+        assert!(parse_layout(&bmp(54, i32::MAX, 2)).is_err());
+        assert!(parse_layout(&bmp(54, 2, i32::MIN)).is_err());
+    }
+
+    #[test]
+    fn images_without_pixels_are_rejected() {
+        let error = parse_layout(&bmp(54, 0, i32::MAX)).err().unwrap();
+        assert_eq!(error.to_string(), "Couldn't process the file as bmp: BMP has no pixels.");
+        assert!(parse_layout(&bmp(54, 2, 0)).is_err());
+    }
+}
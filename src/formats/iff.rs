@@ -0,0 +1,45 @@
+use std::convert::TryInto;
+use std::ops::Range;
+
+/// A chunk of a RIFF or IFF container.
+pub struct Chunk<'a> {
+    pub id: &'a [u8],
+    /// Where the body of the chunk is, clipped to the end of the file.
+    pub body: Range<usize>,
+}
+
+impl<'a> Chunk<'a> {
+    /// Whether this is a list of the given type, such as `movi`.
+    pub fn is_list(&self, data: &[u8], kind: &[u8]) -> bool {
+        (self.id == b"LIST" || self.id == b"RIFF") && data.get(self.body.start..self.body.start + 4) == Some(kind)
+    }
+
+    /// Where the whole chunk is, including its header and padding.
+    pub fn bounds(&self, data: &[u8]) -> Range<usize> {
+        self.body.start - 8..(self.body.end + (self.body.len() & 1)).min(data.len())
+    }
+}
+
+/// Reads every chunk within `range`, such as after the 12 byte header of the container.
+/// 
+/// RIFF (WAV, AVI) stores sizes in little endian, while IFF (AIFF) stores them in big endian.
+/// Either pads chunks to an even length.
+pub fn chunks(data: &[u8], range: Range<usize>, big_endian: bool) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut pos = range.start;
+    let end = range.end.min(data.len());
+
+    while pos + 8 <= end {
+        let size: [u8; 4] = data[pos + 4..pos + 8].try_into().unwrap();
+        let size = if big_endian { u32::from_be_bytes(size) } else { u32::from_le_bytes(size) } as usize;
+
+        // Some encoders leave the size of the last chunk unset while streaming.
+        let start = pos + 8;
+        let body_end = start.saturating_add(size).min(end);
+
+        chunks.push(Chunk { id: &data[pos..pos + 4], body: start..body_end });
+        pos = body_end + (size & 1);
+    }
+
+    chunks
+}
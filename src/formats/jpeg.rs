@@ -0,0 +1,323 @@
+use super::{Format, Decoded};
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use std::ops::Range;
+
+/// JPEG images.
+/// 
+/// Mutates only one kind of data, leaving every marker and header intact. Options are read from `[JpegConfig]`:
+/// 
+/// * `target` - What to mutate. Either `scan` (the default) for the entropy-coded image data,
+///   `quantization` for the quantization tables, or `huffman` for the symbols of the Huffman tables.
+pub struct Jpeg;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    /// Entropy-coded data, between markers.
+    Scan,
+    /// Values of a quantization table, either 8 or 16 bits each.
+    Quantization { wide: bool },
+    /// Symbols of a Huffman table, either for DC or AC coefficients.
+    Huffman { dc: bool },
+}
+
+/// A part of the file that can be mutated.
+struct Region {
+    range: Range<usize>,
+    kind: Kind,
+}
+
+/// A JPEG, with the data to mutate extracted from it.
+pub struct DecodedJpeg {
+    file: Vec<u8>,
+    regions: Vec<Region>,
+    data: Vec<u8>,
+}
+
+fn read_u16(data: &[u8], pos: usize) -> Option<usize> {
+    data.get(pos..pos + 2).map(|bytes| ((bytes[0] as usize) << 8) | bytes[1] as usize)
+}
+
+/// Splits the segments of a JPEG into the regions that can be mutated.
+fn parse_regions(data: &[u8]) -> Result<Vec<Region>, Error> {
+    let truncated = || Error::format("jpeg", "JPEG is truncated.");
+
+    let mut regions = Vec::new();
+    let mut pos = 2;
+
+    while pos < data.len() {
+        if data[pos] != 0xFF {
+            return Err(Error::format("jpeg", format!("Expected a JPEG marker at offset {}.", pos)));
+        }
+
+        // Markers can be preceded by any number of fill bytes.
+        while data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+
+        let marker = *data.get(pos + 1).ok_or_else(truncated)?;
+        pos += 2;
+
+        match marker {
+            0xD9 => break,
+            0x01 | 0xD0..=0xD8 => continue,
+            _ => (),
+        }
+
+        let len = read_u16(data, pos).ok_or_else(truncated)?;
+        let body = pos + 2..pos + len;
+        if len < 2 || body.end > data.len() {
+            return Err(truncated());
+        }
+
+        match marker {
+            // Quantization tables
+            0xDB => {
+                let mut table = body.start;
+                while table < body.end {
+                    let wide = data[table] >> 4 != 0;
+                    let values = table + 1..(table + 1 + if wide { 128 } else { 64 }).min(body.end);
+                    table = values.end;
+                    regions.push(Region { range: values, kind: Kind::Quantization { wide } });
+                }
+            },
+            // Huffman tables
+            0xC4 => {
+                let mut table = body.start;
+                while table + 17 <= body.end {
+                    let dc = data[table] >> 4 == 0;
+                    let count: usize = data[table + 1..table + 17].iter().map(|&count| count as usize).sum();
+                    let symbols = table + 17..(table + 17 + count).min(body.end);
+                    table = symbols.end;
+                    regions.push(Region { range: symbols, kind: Kind::Huffman { dc } });
+                }
+            },
+            // Start of scan, followed by entropy-coded data up until the next marker.
+            0xDA => {
+                let mut start = body.end;
+                let mut end = start;
+
+                while end + 1 < data.len() {
+                    if data[end] != 0xFF {
+                        end += 1;
+                        continue;
+                    }
+
+                    match data[end + 1] {
+                        // Stuffed byte, part of the data.
+                        0x00 => end += 2,
+                        // Restart markers split the data, and are kept as-is.
+                        0xD0..=0xD7 => {
+                            regions.push(Region { range: start..end, kind: Kind::Scan });
+                            end += 2;
+                            start = end;
+                        },
+                        _ => break,
+                    }
+                }
+
+                regions.push(Region { range: start..end, kind: Kind::Scan });
+                pos = end;
+                continue;
+            },
+            _ => (),
+        }
+
+        pos = body.end;
+    }
+
+    Ok(regions)
+}
+
+/// Makes mutated data valid again for its kind.
+fn sanitize(data: &mut [u8], kind: Kind) {
+    match kind {
+        // A 0xFF not followed by 0x00 would be read as a marker.
+        Kind::Scan => {
+            for index in 0..data.len() {
+                if data[index] == 0xFF && data.get(index + 1) != Some(&0x00) {
+                    data[index] = 0xFE;
+                }
+            }
+        },
+        // Quantization values of 0 aren't allowed.
+        Kind::Quantization { wide: false } => {
+            data.iter_mut().filter(|value| **value == 0).for_each(|value| *value = 1);
+        },
+        Kind::Quantization { wide: true } => {
+            for value in data.chunks_mut(2) {
+                if value.iter().all(|&byte| byte == 0) {
+                    *value.last_mut().unwrap() = 1;
+                }
+            }
+        },
+        // DC coefficients only have symbols from 0 to 11.
+        Kind::Huffman { dc: true } => {
+            data.iter_mut().for_each(|symbol| *symbol %= 12);
+        },
+        Kind::Huffman { dc: false } => (),
+    }
+}
+
+impl Format for Jpeg {
+    fn name(&self) -> &str {
+        "jpeg"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(&[0xFF, 0xD8, 0xFF])
+    }
+
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error> {
+        if !self.detect(data) {
+            return Err(Error::format("jpeg", "Not a JPEG file."));
+        }
+
+        let target = cfg.get_option("JpegConfig", "target")
+            .and_then(|target| target.as_str())
+            .map_or("scan", |target| target.as_str());
+
+        if !["scan", "quantization", "huffman"].contains(&target) {
+            return Err(Error::invalid_option("JpegConfig", "target",
+                format!("'scan', 'quantization' or 'huffman', not '{}'", target)));
+        }
+
+        let regions: Vec<Region> = parse_regions(data)?.into_iter()
+            .filter(|region| matches!((target, region.kind),
+                ("scan", Kind::Scan) | ("quantization", Kind::Quantization { .. }) | ("huffman", Kind::Huffman { .. })))
+            .collect();
+
+        let extracted = regions.iter()
+            .flat_map(|region| data[region.range.clone()].iter().cloned())
+            .collect();
+
+        Ok(Box::new(DecodedJpeg { file: data.to_vec(), regions, data: extracted }))
+    }
+}
+
+impl Decoded for DecodedJpeg {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn encode(&self, mut data: Vec<u8>, _cfg: &Configuration) -> Result<Vec<u8>, Error> {
+        if data.len() != self.data.len() {
+            return Err(Error::format("jpeg", "JPEG data changed size while being mutated."));
+        }
+
+        let mut file = self.file.clone();
+        let mut pos = 0;
+
+        for region in &self.regions {
+            let chunk = &mut data[pos..pos + region.range.len()];
+            sanitize(chunk, region.kind);
+            file[region.range.clone()].copy_from_slice(chunk);
+            pos += region.range.len();
+        }
+
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(marker: u8, body: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(&[0xFF, marker]);
+        out.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+        out.extend_from_slice(body);
+    }
+
+    /// A JPEG with an 8-bit and a 16-bit quantization table, a DC and an AC Huffman table,
+    /// and two intervals of scan data split by a restart marker.
+    fn jpeg() -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8];
+        segment(0xE0, b"JFIF\0", &mut out);
+
+        let mut dqt = vec![0x00];
+        dqt.extend(1..=64);
+        dqt.push(0x11);
+        dqt.extend((1..=128).map(|value| value as u8));
+        segment(0xDB, &dqt, &mut out);
+
+        let mut dht = vec![0x00, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 5, 6];
+        dht.extend_from_slice(&[0x10, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x11]);
+        segment(0xC4, &dht, &mut out);
+
+        segment(0xDA, &[1, 1, 0, 0, 63, 0], &mut out);
+        out.extend_from_slice(&[0x12, 0xFF, 0x00, 0x34]);
+        out.extend_from_slice(&[0xFF, 0xD0]);
+        out.extend_from_slice(&[0x56, 0x78]);
+        out.extend_from_slice(&[0xFF, 0xD9]);
+        out
+    }
+
+    #[test]
+    fn regions_of_every_kind() {
+        let file = jpeg();
+        let regions = parse_regions(&file).unwrap();
+
+        let kinds: Vec<Kind> = regions.iter().map(|region| region.kind).collect();
+        assert_eq!(kinds, vec![
+            Kind::Quantization { wide: false },
+            Kind::Quantization { wide: true },
+            Kind::Huffman { dc: true },
+            Kind::Huffman { dc: false },
+            Kind::Scan,
+            Kind::Scan,
+        ]);
+
+        let contents: Vec<&[u8]> = regions.iter().map(|region| &file[region.range.clone()]).collect();
+        assert_eq!(contents[0], &(1..=64).collect::<Vec<u8>>()[..]);
+        assert_eq!(contents[1].len(), 128);
+        assert_eq!(contents[2], &[4, 5, 6]);
+        assert_eq!(contents[3], &[0x01, 0x11]);
+        assert_eq!(contents[4], &[0x12, 0xFF, 0x00, 0x34]);
+        assert_eq!(contents[5], &[0x56, 0x78]);
+    }
+
+    #[test]
+    fn truncated_segments_are_rejected() {
+        let file = jpeg();
+        assert!(parse_regions(&file[..30]).is_err());
+        assert!(parse_regions(&[0xFF, 0xD8, 0x00]).is_err());
+    }
+
+    #[test]
+    fn sanitize_removes_markers_from_scan_data() {
+        let mut data = [0xFF, 0x00, 0xFF, 0xD9, 0x12, 0xFF];
+        sanitize(&mut data, Kind::Scan);
+        assert_eq!(data, [0xFF, 0x00, 0xFE, 0xD9, 0x12, 0xFE]);
+    }
+
+    #[test]
+    fn sanitize_fixes_tables() {
+        let mut narrow = [0, 5, 0];
+        sanitize(&mut narrow, Kind::Quantization { wide: false });
+        assert_eq!(narrow, [1, 5, 1]);
+
+        let mut wide = [0, 0, 0, 5];
+        sanitize(&mut wide, Kind::Quantization { wide: true });
+        assert_eq!(wide, [0, 1, 0, 5]);
+
+        let mut dc = [11, 12, 255];
+        sanitize(&mut dc, Kind::Huffman { dc: true });
+        assert_eq!(dc, [11, 0, 3]);
+    }
+
+    #[test]
+    fn encoding_keeps_every_marker() {
+        let file = jpeg();
+        let config = Configuration::builder().build();
+        let decoded = Jpeg.decode(&file, &config).unwrap();
+        assert_eq!(decoded.data(), &[0x12, 0xFF, 0x00, 0x34, 0x56, 0x78]);
+
+        let encoded = decoded.encode(vec![0xFF; 6], &config).unwrap();
+        assert_eq!(encoded.len(), file.len());
+        assert_eq!(Jpeg.decode(&encoded, &config).unwrap().data(), &[0xFE; 6]);
+        assert!(encoded.ends_with(&[0xFE, 0xFE, 0xFF, 0xD9]));
+    }
+}
@@ -0,0 +1,224 @@
+//! Format-aware bending.
+//! 
+//! Some formats break as soon as their raw bytes are touched. Formats in here decode the
+//! file into the data worth mutating, then encode the mutated data back into a valid file.
+
+mod png;
+mod jpeg;
+mod bmp;
+mod tiff;
+mod pixels;
+mod audio;
+mod iff;
+mod wav;
+mod aiff;
+mod avi;
+
+pub use self::png::Png;
+pub use self::jpeg::Jpeg;
+pub use self::bmp::Bmp;
+pub use self::tiff::Tiff;
+pub use self::wav::Wav;
+pub use self::aiff::Aiff;
+pub use self::avi::{Avi, AviFile, AviChunk};
+
+use super::configuration::Configuration;
+use super::error::Error;
+use super::loaders::Loader;
+
+use std::ops::Range;
+
+/// A file format glitchup knows how to decode.
+pub trait Format: Send + Sync {
+    /// The name used in the `format` option, such as `png`.
+    fn name(&self) -> &str;
+
+    /// Whether `data` looks like a file of this format.
+    fn detect(&self, data: &[u8]) -> bool;
+
+    /// Decodes a file into the data mutations should work on.
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error>;
+}
+
+/// A decoded file, which can be encoded again once mutated.
+pub trait Decoded: Send + Sync {
+    /// The data mutations work on.
+    fn data(&self) -> &[u8];
+
+    /// Encodes a mutated copy of `data()` back into a valid file.
+    fn encode(&self, data: Vec<u8>, cfg: &Configuration) -> Result<Vec<u8>, Error>;
+
+    /// The number of bytes mutations that support the `align` option should keep together,
+    /// such as an audio frame.
+    fn alignment(&self) -> usize {
+        1
+    }
+
+    /// Where each pixel is, if `data()` is made of 8-bit pixels stored row after row.
+    fn pixels(&self) -> Option<Pixels> {
+        None
+    }
+}
+
+/// How decoded pixels are laid out: row after row, without any padding, one byte per channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pixels {
+    pub width: usize,
+    pub channels: usize,
+    /// The order of the channels: `gray`, `graya`, `rgb`, `rgba`, `bgr` or `bgra`.
+    pub order: &'static str,
+}
+
+impl Pixels {
+    /// Describes pixels with channels in `order`, each row being `row_len` bytes long.
+    pub fn new(row_len: usize, order: &'static str) -> Self {
+        let channels = match order {
+            "gray" => 1,
+            "graya" => 2,
+            "rgb" | "bgr" => 3,
+            _ => 4,
+        };
+
+        Pixels { width: row_len / channels, channels, order }
+    }
+}
+
+/// Parts of a file extracted as-is, which are written back in place once mutated.
+pub struct Extracted {
+    file: Vec<u8>,
+    ranges: Vec<Range<usize>>,
+    data: Vec<u8>,
+    alignment: usize,
+    pixels: Option<Pixels>,
+}
+
+impl Extracted {
+    pub fn new(file: &[u8], ranges: Vec<Range<usize>>) -> Self {
+        let data = ranges.iter()
+            .flat_map(|range| file[range.clone()].iter().cloned())
+            .collect();
+
+        Extracted { file: file.to_vec(), ranges, data, alignment: 1, pixels: None }
+    }
+
+    /// Sets the alignment reported to mutations.
+    pub fn aligned(mut self, alignment: usize) -> Self {
+        self.alignment = alignment.max(1);
+        self
+    }
+
+    /// Sets the layout of the pixels reported to mutations.
+    pub fn with_pixels(mut self, pixels: Option<Pixels>) -> Self {
+        self.pixels = pixels;
+        self
+    }
+}
+
+impl Decoded for Extracted {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn encode(&self, data: Vec<u8>, cfg: &Configuration) -> Result<Vec<u8>, Error> {
+        if data.len() != self.data.len() {
+            return Err(Error::format(cfg.get_format().unwrap_or_default(), "Data changed size while being mutated."));
+        }
+
+        let mut file = self.file.clone();
+        let mut pos = 0;
+
+        for range in &self.ranges {
+            file[range.clone()].copy_from_slice(&data[pos..pos + range.len()]);
+            pos += range.len();
+        }
+
+        Ok(file)
+    }
+
+    fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    fn pixels(&self) -> Option<Pixels> {
+        self.pixels
+    }
+}
+
+/// Fits mutated data back to the size of the `original` data, in case mutations changed it.
+/// 
+/// Data that grew is cut short, while data that shrunk is padded with the end of the original.
+/// Either way, everything after an insertion or deletion stays shifted, which is what desyncs rows.
+pub fn fit(mut data: Vec<u8>, original: &[u8]) -> Vec<u8> {
+    if data.len() < original.len() {
+        data.extend_from_slice(&original[data.len()..]);
+    }
+
+    data.truncate(original.len());
+    data
+}
+
+/// Every format that can be decoded.
+pub fn formats() -> Vec<Box<dyn Format>> {
+    vec![
+        Box::new(Png),
+        Box::new(Jpeg),
+        Box::new(Bmp),
+        Box::new(Tiff),
+        Box::new(Wav),
+        Box::new(Aiff),
+        Box::new(Avi),
+        // Add more formats here.
+    ]
+}
+
+/// Decodes `data` using the `format` option.
+/// 
+/// `raw` (the default) means no decoding at all, while `auto` picks the format from the data itself,
+/// falling back to `raw` if nothing matches.
+pub fn decode(cfg: &Configuration, data: &[u8]) -> Result<Option<Box<dyn Decoded>>, Error> {
+    let name = cfg.get_format()?;
+
+    let format = match name {
+        "raw" => None,
+        "auto" => formats().into_iter().find(|format| format.detect(data)),
+        _ => Some(formats().into_iter()
+            .find(|format| format.name() == name)
+            .ok_or_else(|| Error::Config(format!("Unknown format '{}'.", name)))?),
+    };
+
+    format.map(|format| format.decode(data, cfg)).transpose()
+}
+
+/// Options describing the decoded data, which mutations read like any other option.
+/// 
+/// These are `align`, along with `width`, `channels` and `channel_order` for images.
+/// Options already set globally are left out, so they can still be overridden.
+pub fn derived_options(cfg: &Configuration, decoded: Option<&dyn Decoded>) -> Vec<(String, toml::Value)> {
+    let mut options = Vec::new();
+
+    if let Some(decoded) = decoded {
+        if decoded.alignment() > 1 {
+            options.push(("align".to_string(), toml::Value::Integer(decoded.alignment() as i64)));
+        }
+
+        if let Some(pixels) = decoded.pixels() {
+            options.push(("width".to_string(), toml::Value::Integer(pixels.width as i64)));
+            options.push(("channels".to_string(), toml::Value::Integer(pixels.channels as i64)));
+            options.push(("channel_order".to_string(), toml::Value::String(pixels.order.into())));
+        }
+    }
+
+    options.retain(|(key, _)| cfg.get(key).is_none());
+    options
+}
+
+/// Reads and decodes the input file, as in `decode`. The file isn't read at all for `raw`.
+pub fn decode_input(cfg: &Configuration) -> Result<Option<Box<dyn Decoded>>, Error> {
+    if cfg.get_format()? == "raw" {
+        return Ok(None);
+    }
+
+    let input = cfg.get_inputfile()?;
+    let data = Loader::read_file(input).map_err(|err| Error::io(input, err))?;
+    decode(cfg, &data)
+}
@@ -0,0 +1,60 @@
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use std::ops::Range;
+
+/// Where each row of an uncompressed image is stored.
+pub struct PixelLayout {
+    pub width: usize,
+    pub height: usize,
+    pub bits_per_pixel: usize,
+    /// The offset of each row within the file, from top to bottom.
+    pub rows: Vec<usize>,
+}
+
+/// A rectangle of pixels, as (x, y, width, height).
+pub type Rect = (usize, usize, usize, usize);
+
+impl PixelLayout {
+    /// The length of a row in bytes, excluding any padding, or `None` if it's too large to be stored.
+    pub fn row_len(&self) -> Option<usize> {
+        self.width.checked_mul(self.bits_per_pixel).map(|bits| bits.div_ceil(8))
+    }
+
+    /// The byte ranges covering a rectangle of pixels, or every pixel if there's no rectangle.
+    /// 
+    /// The rectangle is clipped to the image. Padding at the end of rows is never included.
+    pub fn ranges(&self, rect: Option<Rect>) -> Vec<Range<usize>> {
+        let (x, y, width, height) = rect.unwrap_or((0, 0, self.width, self.height));
+
+        let x_end = x.saturating_add(width).min(self.width);
+        let y_end = y.saturating_add(height).min(self.height);
+        if x >= x_end || y >= y_end {
+            return Vec::new();
+        }
+
+        let start = x * self.bits_per_pixel / 8;
+        let end = (x_end * self.bits_per_pixel).div_ceil(8);
+
+        self.rows[y..y_end].iter()
+            .map(|row| row + start..row + end)
+            .collect()
+    }
+}
+
+/// Reads the `region` option as `[x, y, width, height]`, under `category` or globally.
+pub fn get_region(cfg: &Configuration, category: &str) -> Result<Option<Rect>, Error> {
+    let region = match cfg.get_option(category, "region") {
+        Some(region) => region,
+        None => return Ok(None),
+    };
+
+    let values: Option<Vec<usize>> = region.as_list()
+        .map(|list| list.iter().map(|value| value.as_int().filter(|int| **int >= 0).map(|int| *int as usize)).collect())
+        .unwrap_or(None);
+
+    match values.as_deref() {
+        Some(&[x, y, width, height]) => Ok(Some((x, y, width, height))),
+        _ => Err(Error::invalid_option(category, "region", "[x, y, width, height]")),
+    }
+}
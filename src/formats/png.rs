@@ -0,0 +1,516 @@
+use super::{Format, Decoded, Pixels};
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// The starting position and step of each pass of Adam7 interlacing, as (x, y, dx, dy).
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2),
+];
+
+/// PNG images.
+/// 
+/// Mutates the filtered scanlines, rather than the compressed stream. Options are read from `[PngConfig]`:
+/// 
+/// * `preserve_filters` - Whether to restore the filter type of each scanline after mutating.
+///   Otherwise invalid filter types are wrapped around, keeping the classic filter glitches.
+/// * `unfilter` - Whether to mutate the pixels themselves, rather than the filtered scanlines.
+///   Required by mutations working on pixels, such as `PixelSort`. Interlaced PNGs aren't supported.
+pub struct Png;
+
+struct Chunk {
+    kind: [u8; 4],
+    data: Vec<u8>,
+}
+
+impl Chunk {
+    fn write(kind: &[u8], data: &[u8], out: &mut Vec<u8>) {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(kind);
+        hasher.update(data);
+
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        out.extend_from_slice(&hasher.finalize().to_be_bytes());
+    }
+}
+
+/// A PNG, with its image data inflated.
+pub struct DecodedPng {
+    /// Every chunk apart from `IDAT`.
+    chunks: Vec<Chunk>,
+    /// Where the image data goes within `chunks`.
+    idat_index: usize,
+    /// The inflated image data, being scanlines each starting with their filter type.
+    scanlines: Vec<u8>,
+    /// The offset of each scanline's filter type.
+    rows: Vec<usize>,
+    /// The rows of pixels without any filter, if `unfilter` was set.
+    unfiltered: Option<(Vec<u8>, Option<Pixels>)>,
+}
+
+/// The parts of the image header needed to find each scanline.
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: usize,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn parse(ihdr: &[u8]) -> Result<Self, Error> {
+        if ihdr.len() < 13 {
+            return Err(Error::format("png", "PNG has an invalid IHDR chunk."));
+        }
+
+        let header = Header {
+            width: u32::from_be_bytes(ihdr[0..4].try_into().unwrap()) as usize,
+            height: u32::from_be_bytes(ihdr[4..8].try_into().unwrap()) as usize,
+            bit_depth: ihdr[8] as usize,
+            color_type: ihdr[9],
+            interlaced: ihdr[12] != 0,
+        };
+
+        header.channels()?;
+        Ok(header)
+    }
+
+    fn channels(&self) -> Result<usize, Error> {
+        match self.color_type {
+            0 | 3 => Ok(1),
+            4 => Ok(2),
+            2 => Ok(3),
+            6 => Ok(4),
+            other => Err(Error::format("png", format!("PNG has an invalid color type {}.", other))),
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels().unwrap_or(1) * self.bit_depth
+    }
+}
+
+fn parse_chunks(data: &[u8]) -> Result<Vec<Chunk>, Error> {
+    let truncated = || Error::format("png", "PNG is truncated.");
+
+    let mut chunks = Vec::new();
+    let mut pos = SIGNATURE.len();
+
+    while pos < data.len() {
+        let header = data.get(pos..pos + 8).ok_or_else(truncated)?;
+        let len = u32::from_be_bytes(header[0..4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        let body = data.get(pos + 8..pos + 8 + len).ok_or_else(truncated)?;
+
+        chunks.push(Chunk { kind, data: body.to_vec() });
+        pos += 12 + len;
+
+        if &kind == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(chunks)
+}
+
+/// Computes the offset of each scanline's filter type from the image header.
+fn row_offsets(header: &Header) -> Vec<usize> {
+    let (width, height) = (header.width, header.height);
+    let bits_per_pixel = header.bits_per_pixel();
+
+    let passes: Vec<(usize, usize)> = if !header.interlaced {
+        vec![(width, height)]
+    } else {
+        ADAM7.iter().map(|&(x, y, dx, dy)| (
+            if width > x { (width - x).div_ceil(dx) } else { 0 },
+            if height > y { (height - y).div_ceil(dy) } else { 0 },
+        )).collect()
+    };
+
+    let mut rows = Vec::new();
+    let mut pos = 0;
+
+    for (width, height) in passes {
+        if width == 0 {
+            continue;
+        }
+
+        let row_len = 1 + (width * bits_per_pixel).div_ceil(8);
+        for _ in 0..height {
+            rows.push(pos);
+            pos += row_len;
+        }
+    }
+
+    rows.push(pos);
+    rows
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the filter of every scanline, returning the rows of pixels without their filter types.
+fn unfilter(scanlines: &[u8], rows: &[usize], row_len: usize, bytes_per_pixel: usize) -> Result<Vec<u8>, Error> {
+    let mut pixels: Vec<u8> = Vec::with_capacity(rows.len() * row_len);
+    let mut previous = vec![0; row_len];
+
+    for &row in rows {
+        let mut line = scanlines[row + 1..row + 1 + row_len].to_vec();
+
+        for i in 0..row_len {
+            let left = if i >= bytes_per_pixel { line[i - bytes_per_pixel] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+
+            line[i] = line[i].wrapping_add(match scanlines[row] {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                other => return Err(Error::format("png", format!("PNG has an invalid filter type {}.", other))),
+            });
+        }
+
+        pixels.extend_from_slice(&line);
+        previous = line;
+    }
+
+    Ok(pixels)
+}
+
+impl Format for Png {
+    fn name(&self) -> &str {
+        "png"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(&SIGNATURE)
+    }
+
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error> {
+        if !self.detect(data) {
+            return Err(Error::format("png", "Not a PNG file."));
+        }
+
+        let all_chunks = parse_chunks(data)?;
+
+        let ihdr = all_chunks.iter()
+            .find(|chunk| &chunk.kind == b"IHDR")
+            .ok_or_else(|| Error::format("png", "PNG has no IHDR chunk."))?;
+        let header = Header::parse(&ihdr.data)?;
+        let mut rows = row_offsets(&header);
+        let expected_len = rows.pop().unwrap_or(0);
+
+        let idat_index = all_chunks.iter()
+            .position(|chunk| &chunk.kind == b"IDAT")
+            .ok_or_else(|| Error::format("png", "PNG has no IDAT chunk."))?;
+
+        let (idat, chunks): (Vec<Chunk>, Vec<Chunk>) = all_chunks.into_iter()
+            .partition(|chunk| &chunk.kind == b"IDAT");
+
+        let compressed: Vec<u8> = idat.into_iter().flat_map(|chunk| chunk.data).collect();
+        let mut scanlines = Vec::new();
+        ZlibDecoder::new(&compressed[..])
+            .read_to_end(&mut scanlines)
+            .map_err(|err| Error::format("png", format!("Couldn't inflate PNG data: {}", err)))?;
+
+        if scanlines.len() < expected_len {
+            return Err(Error::format("png", format!(
+                "PNG data is {} bytes long, expected {}.", scanlines.len(), expected_len)));
+        }
+
+        let unfilter_option = cfg.get_option("PngConfig", "unfilter")
+            .and_then(|unfilter| unfilter.as_bool())
+            .cloned()
+            .unwrap_or(false);
+
+        let unfiltered = if unfilter_option {
+            if header.interlaced {
+                return Err(Error::format("png", "'unfilter' doesn't support interlaced PNGs."));
+            }
+
+            let row_len = (header.width * header.bits_per_pixel()).div_ceil(8);
+            let bytes_per_pixel = header.bits_per_pixel().div_ceil(8).max(1);
+
+            // Only 8-bit samples can be read as pixels, and palettes don't store colors.
+            let order = match header.color_type {
+                0 => Some("gray"),
+                4 => Some("graya"),
+                2 => Some("rgb"),
+                6 => Some("rgba"),
+                _ => None,
+            }.filter(|_| header.bit_depth == 8);
+
+            let pixels = order.map(|order| Pixels::new(row_len, order));
+            Some((unfilter(&scanlines, &rows, row_len, bytes_per_pixel)?, pixels))
+        } else {
+            None
+        };
+
+        Ok(Box::new(DecodedPng { chunks, idat_index, scanlines, rows, unfiltered }))
+    }
+}
+
+impl Decoded for DecodedPng {
+    fn data(&self) -> &[u8] {
+        match &self.unfiltered {
+            Some((pixels, _)) => pixels,
+            None => &self.scanlines,
+        }
+    }
+
+    fn pixels(&self) -> Option<Pixels> {
+        self.unfiltered.as_ref().and_then(|(_, pixels)| *pixels)
+    }
+
+    fn encode(&self, data: Vec<u8>, cfg: &Configuration) -> Result<Vec<u8>, Error> {
+        if data.len() != self.data().len() {
+            return Err(Error::format("png", "PNG data changed size while being mutated."));
+        }
+
+        // Unfiltered rows are stored again without any filter.
+        let mut data = if self.unfiltered.is_some() {
+            let row_len = data.len() / self.rows.len().max(1);
+            let mut scanlines = self.scanlines.clone();
+
+            for (&row, pixels) in self.rows.iter().zip(data.chunks(row_len.max(1))) {
+                scanlines[row] = 0;
+                scanlines[row + 1..row + 1 + pixels.len()].copy_from_slice(pixels);
+            }
+
+            scanlines
+        } else {
+            data
+        };
+
+        let preserve = cfg.get_option("PngConfig", "preserve_filters")
+            .and_then(|preserve| preserve.as_bool())
+            .cloned()
+            .unwrap_or(false);
+
+        // Only filter types 0 to 4 exist, and anything else can't be decoded.
+        if self.unfiltered.is_none() {
+            for &row in &self.rows {
+                data[row] = if preserve { self.scanlines[row] } else { data[row] % 5 };
+            }
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let compressed = encoder.write_all(&data)
+            .and_then(|_| encoder.finish())
+            .map_err(|err| Error::format("png", format!("Couldn't deflate PNG data: {}", err)))?;
+
+        let mut out = SIGNATURE.to_vec();
+        for (index, chunk) in self.chunks.iter().enumerate() {
+            if index == self.idat_index {
+                Chunk::write(b"IDAT", &compressed, &mut out);
+            }
+            Chunk::write(&chunk.kind, &chunk.data, &mut out);
+        }
+
+        if self.idat_index >= self.chunks.len() {
+            Chunk::write(b"IDAT", &compressed, &mut out);
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Filters a row of pixels with `filter`, the reverse of `unfilter`.
+    fn filter(filter: u8, line: &[u8], previous: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+        let mut out = vec![filter];
+
+        for i in 0..line.len() {
+            let left = if i >= bytes_per_pixel { line[i - bytes_per_pixel] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+
+            out.push(line[i].wrapping_sub(match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                _ => paeth(left, up, up_left),
+            }));
+        }
+
+        out
+    }
+
+    /// An RGB image whose rows use every filter type in turn.
+    fn pixels(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height * 3).map(|index| (index * 37 % 256) as u8).collect()
+    }
+
+    fn scanlines(pixels: &[u8], width: usize) -> Vec<u8> {
+        let row_len = width * 3;
+        let mut previous = vec![0; row_len];
+        let mut scanlines = Vec::new();
+
+        for (index, line) in pixels.chunks(row_len).enumerate() {
+            scanlines.extend(filter(index as u8 % 5, line, &previous, 3));
+            previous = line.to_vec();
+        }
+
+        scanlines
+    }
+
+    fn png(width: usize, height: usize, scanlines: &[u8]) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(scanlines).unwrap();
+
+        let mut out = SIGNATURE.to_vec();
+        Chunk::write(b"IHDR", &ihdr, &mut out);
+        Chunk::write(b"IDAT", &encoder.finish().unwrap(), &mut out);
+        Chunk::write(b"IEND", &[], &mut out);
+        out
+    }
+
+    fn empty() -> Configuration {
+        Configuration::builder().build()
+    }
+
+    fn inflate(file: &[u8]) -> Vec<u8> {
+        let compressed: Vec<u8> = parse_chunks(file).unwrap().into_iter()
+            .filter(|chunk| &chunk.kind == b"IDAT")
+            .flat_map(|chunk| chunk.data)
+            .collect();
+
+        let mut data = Vec::new();
+        ZlibDecoder::new(&compressed[..]).read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn row_offsets_of_plain_image() {
+        let header = Header { width: 3, height: 2, bit_depth: 8, color_type: 2, interlaced: false };
+        assert_eq!(row_offsets(&header), vec![0, 10, 20]);
+
+        let header = Header { width: 3, height: 2, bit_depth: 1, color_type: 0, interlaced: false };
+        assert_eq!(row_offsets(&header), vec![0, 2, 4]);
+    }
+
+    #[test]
+    fn row_offsets_of_interlaced_image() {
+        // The passes of an 8x8 image are 1x1, 1x1, 2x1, 2x2, 4x2, 4x4 and 8x4 pixels.
+        let header = Header { width: 8, height: 8, bit_depth: 8, color_type: 0, interlaced: true };
+        assert_eq!(row_offsets(&header), vec![0, 2, 4, 7, 10, 13, 18, 23, 28, 33, 38, 43, 52, 61, 70, 79]);
+
+        // A single pixel only appears in the first pass.
+        let header = Header { width: 1, height: 1, bit_depth: 8, color_type: 0, interlaced: true };
+        assert_eq!(row_offsets(&header), vec![0, 2]);
+    }
+
+    #[test]
+    fn unfilter_reverses_every_filter() {
+        let (width, height) = (7, 10);
+        let pixels = pixels(width, height);
+        let scanlines = scanlines(&pixels, width);
+        let header = Header { width, height, bit_depth: 8, color_type: 2, interlaced: false };
+
+        let mut rows = row_offsets(&header);
+        rows.pop();
+
+        assert_eq!(unfilter(&scanlines, &rows, width * 3, 3).unwrap(), pixels);
+    }
+
+    #[test]
+    fn unfilter_rejects_invalid_filters() {
+        let scanlines = [5, 1, 2, 3];
+        assert!(unfilter(&scanlines, &[0], 3, 3).is_err());
+    }
+
+    #[test]
+    fn encoded_output_is_valid() {
+        let (width, height) = (5, 6);
+        let file = png(width, height, &scanlines(&pixels(width, height), width));
+        let decoded = Png.decode(&file, &empty()).unwrap();
+
+        // Breaks every filter type, along with the pixels.
+        let mut data = decoded.data().to_vec();
+        for (index, byte) in data.iter_mut().enumerate() {
+            *byte = byte.wrapping_add((index * 3) as u8);
+        }
+
+        let encoded = decoded.encode(data.clone(), &empty()).unwrap();
+
+        let mut pos = SIGNATURE.len();
+        while pos < encoded.len() {
+            let len = u32::from_be_bytes(encoded[pos..pos + 4].try_into().unwrap()) as usize;
+            let crc = crc32fast::hash(&encoded[pos + 4..pos + 8 + len]);
+            assert_eq!(encoded[pos + 8 + len..pos + 12 + len], crc.to_be_bytes());
+            pos += 12 + len;
+        }
+        assert_eq!(pos, encoded.len());
+
+        let inflated = inflate(&encoded);
+        for row in (0..height).map(|row| row * (1 + width * 3)) {
+            assert_eq!(inflated[row], data[row] % 5);
+            assert_eq!(inflated[row + 1..row + 1 + width * 3], data[row + 1..row + 1 + width * 3]);
+        }
+
+        assert!(Png.decode(&encoded, &empty()).is_ok());
+    }
+
+    #[test]
+    fn preserve_filters_restores_filter_types() {
+        let (width, height) = (4, 5);
+        let scanlines = scanlines(&pixels(width, height), width);
+        let file = png(width, height, &scanlines);
+        let config = Configuration::builder().option("PngConfig.preserve_filters", true).build();
+        let decoded = Png.decode(&file, &config).unwrap();
+
+        let data: Vec<u8> = decoded.data().iter().map(|byte| byte.wrapping_add(1)).collect();
+        let inflated = inflate(&decoded.encode(data, &config).unwrap());
+
+        for row in (0..height).map(|row| row * (1 + width * 3)) {
+            assert_eq!(inflated[row], scanlines[row]);
+        }
+    }
+
+    #[test]
+    fn unfiltered_pixels_round_trip() {
+        let (width, height) = (6, 4);
+        let pixels = pixels(width, height);
+        let file = png(width, height, &scanlines(&pixels, width));
+        let config = Configuration::builder().option("PngConfig.unfilter", true).build();
+
+        let decoded = Png.decode(&file, &config).unwrap();
+        assert_eq!(decoded.data(), &pixels[..]);
+        assert_eq!(decoded.pixels(), Some(Pixels::new(width * 3, "rgb")));
+
+        let encoded = decoded.encode(pixels.clone(), &config).unwrap();
+        assert_eq!(Png.decode(&encoded, &config).unwrap().data(), &pixels[..]);
+    }
+}
@@ -0,0 +1,244 @@
+use super::{Format, Decoded, Extracted, Pixels};
+use super::pixels::{self, PixelLayout};
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use std::convert::TryInto;
+use std::ops::Range;
+
+/// Baseline TIFF images.
+/// 
+/// Mutates only the strips of the first image, leaving the header and every IFD intact.
+/// Options are read from `[TiffConfig]`:
+/// 
+/// * `region` - Only mutates the pixels within `[x, y, width, height]`, counted from the top left.
+///   Requires the image to be uncompressed.
+pub struct Tiff;
+
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+
+/// Reads integers in the byte order of the file.
+struct Reader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = self.data.get(pos..pos + 2)?.try_into().unwrap();
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes = self.data.get(pos..pos + 4)?.try_into().unwrap();
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    /// Reads every value of an IFD entry, as long as they're BYTE, SHORT or LONG.
+    fn values(&self, entry: usize) -> Option<Vec<usize>> {
+        let size = match self.u16(entry + 2)? {
+            1 => 1,
+            3 => 2,
+            4 => 4,
+            _ => return None,
+        };
+        let count = self.u32(entry + 4)? as usize;
+
+        // Values that fit in 4 bytes are stored in the entry itself.
+        let start = if size * count <= 4 { entry + 8 } else { self.u32(entry + 8)? as usize };
+
+        (0..count).map(|index| {
+            let pos = start + index * size;
+            match size {
+                1 => self.data.get(pos).map(|&byte| byte as usize),
+                2 => self.u16(pos).map(|value| value as usize),
+                _ => self.u32(pos).map(|value| value as usize),
+            }
+        }).collect()
+    }
+}
+
+/// Reads the first IFD, returning each of its tags along with their values.
+fn parse_ifd(data: &[u8]) -> Result<Vec<(u16, Vec<usize>)>, Error> {
+    let truncated = || Error::format("tiff", "TIFF is truncated.");
+
+    let reader = Reader { data, little_endian: data.starts_with(b"II") };
+    let ifd = reader.u32(4).ok_or_else(truncated)? as usize;
+    let count = reader.u16(ifd).ok_or_else(truncated)? as usize;
+
+    (0..count).map(|index| {
+        let entry = ifd + 2 + index * 12;
+        let tag = reader.u16(entry).ok_or_else(truncated)?;
+        Ok((tag, reader.values(entry).unwrap_or_default()))
+    }).collect()
+}
+
+impl Format for Tiff {
+    fn name(&self) -> &str {
+        "tiff"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+    }
+
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error> {
+        if !self.detect(data) {
+            return Err(Error::format("tiff", "Not a TIFF file."));
+        }
+
+        let tags = parse_ifd(data)?;
+        let tag = |id: u16| tags.iter().find(|(tag, _)| *tag == id).map(|(_, values)| values.clone());
+        let single = |id: u16, default: usize| tag(id).and_then(|values| values.first().cloned()).unwrap_or(default);
+
+        let offsets = tag(STRIP_OFFSETS)
+            .ok_or_else(|| Error::format("tiff", "TIFF has no strips. Tiled TIFFs aren't supported."))?;
+        let counts = tag(STRIP_BYTE_COUNTS)
+            .ok_or_else(|| Error::format("tiff", "TIFF has no StripByteCounts."))?;
+
+        let strips: Vec<Range<usize>> = offsets.iter().zip(counts.iter())
+            .map(|(&offset, &count)| offset..offset.saturating_add(count).min(data.len()))
+            .filter(|strip| strip.start < strip.end)
+            .collect();
+
+        let width = single(IMAGE_WIDTH, 0);
+        let height = single(IMAGE_LENGTH, 0);
+        let samples = single(SAMPLES_PER_PIXEL, 1);
+        let bits = tag(BITS_PER_SAMPLE).unwrap_or_default();
+        let uncompressed = single(COMPRESSION, 1) == 1 && single(PLANAR_CONFIGURATION, 1) == 1;
+
+        // Only uncompressed 8-bit grayscale or RGB samples can be read as pixels.
+        let order = match (single(PHOTOMETRIC_INTERPRETATION, 1), samples) {
+            (0, 1) | (1, 1) => Some("gray"),
+            (0, 2) | (1, 2) => Some("graya"),
+            (2, 3) => Some("rgb"),
+            (2, 4) => Some("rgba"),
+            _ => None,
+        }.filter(|_| uncompressed && bits.iter().all(|&bits| bits == 8));
+
+        let region = pixels::get_region(cfg, "TiffConfig")?;
+        if region.is_none() {
+            let pixels = order.map(|order| Pixels::new(width * samples, order));
+            return Ok(Box::new(Extracted::new(data, strips).with_pixels(pixels)));
+        }
+
+        if !uncompressed {
+            return Err(Error::format("tiff", "'region' requires an uncompressed TIFF with interleaved samples."));
+        }
+
+        // Some writers only store a single value for every sample.
+        let bits_per_pixel = if bits.len() == samples {
+            bits.iter().sum()
+        } else {
+            bits.first().cloned().unwrap_or(1) * samples
+        };
+        let rows_per_strip = single(ROWS_PER_STRIP, height).max(1);
+
+        let mut layout = PixelLayout { width, height, bits_per_pixel, rows: Vec::new() };
+        let truncated = || Error::format("tiff", "TIFF is truncated.");
+        let row_len = layout.row_len().ok_or_else(truncated)?;
+
+        // Empty rows would all fit in the file, however many the header claims.
+        if row_len == 0 || height == 0 {
+            return Err(Error::format("tiff", "TIFF has no pixels."));
+        }
+
+        // Rows are packed within strips, without any padding in-between.
+        // Strips can be anywhere in the file, so every row is checked to be within it.
+        layout.rows = (0..height)
+            .map(|y| {
+                let strip = offsets.get(y / rows_per_strip)
+                    .ok_or_else(|| Error::format("tiff", "TIFF has fewer strips than rows require."))?;
+
+                (y % rows_per_strip).checked_mul(row_len)
+                    .and_then(|row| row.checked_add(*strip))
+                    .filter(|row| row.checked_add(row_len).is_some_and(|end| end <= data.len()))
+                    .ok_or_else(truncated)
+            })
+            .collect::<Result<_, _>>()?;
+
+        let ranges = layout.ranges(region);
+        let pixels = order.map(|order| Pixels::new(ranges.first().map_or(0, |row| row.len()), order));
+
+        Ok(Box::new(Extracted::new(data, ranges).with_pixels(pixels)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian 2x2 grayscale TIFF, with one strip for each row at `strips`.
+    fn tiff(strips: [u32; 2]) -> Vec<u8> {
+        let entries: [(u16, u16, u32, u32); 7] = [
+            (IMAGE_WIDTH, 3, 1, 2),
+            (IMAGE_LENGTH, 3, 1, 2),
+            (BITS_PER_SAMPLE, 3, 1, 8),
+            (PHOTOMETRIC_INTERPRETATION, 3, 1, 1),
+            (STRIP_OFFSETS, 4, 2, 98),
+            (ROWS_PER_STRIP, 3, 1, 1),
+            (STRIP_BYTE_COUNTS, 3, 2, 0x0002_0002),
+        ];
+
+        let mut out = b"II*\0".to_vec();
+        out.extend_from_slice(&8u32.to_le_bytes());
+        out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in entries.iter() {
+            out.extend_from_slice(&tag.to_le_bytes());
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&count.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&[0; 4]);
+
+        out.extend_from_slice(&strips[0].to_le_bytes());
+        out.extend_from_slice(&strips[1].to_le_bytes());
+        out.extend_from_slice(&[1, 2, 3, 4]);
+        out
+    }
+
+    fn region() -> Configuration {
+        Configuration::builder().option("TiffConfig.region", vec![0, 0, 2, 2]).build()
+    }
+
+    #[test]
+    fn region_covers_every_row() {
+        let file = tiff([108, 106]);
+        assert_eq!(file.len(), 110);
+
+        let decoded = Tiff.decode(&file, &region()).unwrap();
+        assert_eq!(decoded.data(), &[3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn rows_outside_the_file_are_rejected() {
+        // Only the last row is within the file.
+        assert!(Tiff.decode(&tiff([10_000, 106]), &region()).is_err());
+        assert!(Tiff.decode(&tiff([u32::MAX, 106]), &region()).is_err());
+        assert!(Tiff.decode(&tiff([109, 106]), &region()).is_err());
+    }
+
+    #[test]
+    fn images_without_pixels_are_rejected() {
+        let mut file = tiff([108, 106]);
+        // No columns, but as many rows as a LONG can hold, all in the first strip.
+        file[18..20].copy_from_slice(&0u16.to_le_bytes());
+        for &entry in &[22, 70] {
+            file[entry + 2..entry + 4].copy_from_slice(&4u16.to_le_bytes());
+            file[entry + 8..entry + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+
+        let error = Tiff.decode(&file, &region()).err().unwrap();
+        assert_eq!(error.to_string(), "Couldn't process the file as tiff: TIFF has no pixels.");
+    }
+}
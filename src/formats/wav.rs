@@ -0,0 +1,122 @@
+use super::{Format, Decoded, Extracted};
+use super::{audio, iff};
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+
+use std::convert::TryInto;
+
+/// WAV (RIFF) audio.
+/// 
+/// Mutates only the samples in the `data` chunk, leaving every other chunk intact.
+/// Options are read from `[WavConfig]`:
+/// 
+/// * `sample_aligned` - Whether `Reverse`, `Shift`, `Swap` and `Loops` work on whole frames,
+///   rather than splitting samples. Defaults to `true`.
+pub struct Wav;
+
+fn read_u16(data: &[u8], pos: usize) -> Option<usize> {
+    data.get(pos..pos + 2).map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+impl Format for Wav {
+    fn name(&self) -> &str {
+        "wav"
+    }
+
+    fn detect(&self, data: &[u8]) -> bool {
+        data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE")
+    }
+
+    fn decode(&self, data: &[u8], cfg: &Configuration) -> Result<Box<dyn Decoded>, Error> {
+        if !self.detect(data) {
+            return Err(Error::format("wav", "Not a WAV file."));
+        }
+
+        let chunks = iff::chunks(data, 12..data.len(), false);
+
+        let fmt = chunks.iter().find(|chunk| chunk.id == b"fmt ")
+            .ok_or_else(|| Error::format("wav", "WAV has no 'fmt ' chunk."))?;
+        let samples = chunks.iter().find(|chunk| chunk.id == b"data")
+            .ok_or_else(|| Error::format("wav", "WAV has no 'data' chunk."))?;
+
+        let truncated = || Error::format("wav", "WAV is truncated.");
+        let channels = read_u16(data, fmt.body.start + 2).ok_or_else(truncated)?;
+        let block_align = read_u16(data, fmt.body.start + 12).ok_or_else(truncated)?;
+        let bits_per_sample = read_u16(data, fmt.body.start + 14).ok_or_else(truncated)?;
+
+        // The block alignment already is the size of a frame, unless the writer left it unset.
+        let frame = if block_align > 0 { block_align } else { channels * bits_per_sample.div_ceil(8) };
+        let alignment = audio::alignment(cfg, "WavConfig", frame)?;
+
+        Ok(Box::new(Extracted::new(data, vec![samples.body.clone()]).aligned(alignment)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 16-bit stereo WAV, with an odd-sized chunk before the `data` chunk claiming `size` bytes.
+    fn wav(block_align: u16, size: u32) -> Vec<u8> {
+        let mut out = b"RIFF\0\0\0\0WAVE".to_vec();
+
+        out.extend_from_slice(b"fmt ");
+        out.extend_from_slice(&16u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&44_100u32.to_le_bytes());
+        out.extend_from_slice(&(44_100 * 4u32).to_le_bytes());
+        out.extend_from_slice(&block_align.to_le_bytes());
+        out.extend_from_slice(&16u16.to_le_bytes());
+
+        // Padded to an even length.
+        out.extend_from_slice(b"note");
+        out.extend_from_slice(&3u32.to_le_bytes());
+        out.extend_from_slice(b"abc\0");
+
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend(1..=8);
+
+        let riff = (out.len() - 8) as u32;
+        out[4..8].copy_from_slice(&riff.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn samples_are_found_after_padded_chunks() {
+        let file = wav(4, 8);
+        let decoded = Wav.decode(&file, &Configuration::builder().build()).unwrap();
+
+        assert_eq!(decoded.data(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(decoded.alignment(), 4);
+
+        let encoded = decoded.encode(vec![8, 7, 6, 5, 4, 3, 2, 1], &Configuration::builder().build()).unwrap();
+        assert_eq!(encoded[..file.len() - 8], file[..file.len() - 8]);
+        assert_eq!(encoded[file.len() - 8..], [8, 7, 6, 5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn truncated_data_is_clipped_to_the_file() {
+        let decoded = Wav.decode(&wav(4, 1000), &Configuration::builder().build()).unwrap();
+
+        assert_eq!(decoded.data(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn frames_are_kept_together_unless_disabled() {
+        let decoded = Wav.decode(&wav(0, 8), &Configuration::builder().build()).unwrap();
+        assert_eq!(decoded.alignment(), 4);
+
+        let config = Configuration::builder().option("WavConfig.sample_aligned", false).build();
+        assert_eq!(Wav.decode(&wav(4, 8), &config).unwrap().alignment(), 1);
+    }
+
+    #[test]
+    fn files_without_samples_are_rejected() {
+        // Ends right after the `fmt ` chunk.
+        assert!(Wav.decode(&wav(4, 8)[..36], &Configuration::builder().build()).is_err());
+        assert!(Wav.decode(b"RIFF\0\0\0\0AVI ", &Configuration::builder().build()).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+//! A databending library.
+//! 
+//! Mutations are configured exactly as they would be in `Options.toml`,
+//! either by loading a file or by building the configuration in code:
+//! 
+//! ```no_run
+//! use glitchup::{Configuration, bend_bytes};
+//! 
+//! let config = Configuration::builder()
+//!     .mutations(&[&["Shift", "Chaos"], &["Reverse"]])
+//!     .option("iterations", vec![1, 10])
+//!     .option("chunksize", 1000)
+//!     .seed(42)
+//!     .build();
+//! 
+//! let data = std::fs::read("image.jpg").unwrap();
+//! 
+//! // One result for each list of mutations.
+//! for bent in bend_bytes(&config, &data).unwrap() {
+//!     println!("{} (seed {})", bent.log.join("---"), bent.seed);
+//! }
+//! ```
+
+pub mod benders;
+pub mod mutations;
+pub mod loaders;
+pub mod configuration;
+pub mod replay;
+pub mod registry;
+pub mod formats;
+pub mod error;
+pub mod targeting;
+pub mod batch;
+mod validation;
+mod distribution;
+mod naming;
+
+pub use benders::{KaBender, Bent, bend_bytes, bend_file};
+pub use configuration::{Configuration, ConfigurationBuilder};
+pub use mutations::{Mutation, OptionSpec, OptionKind};
+pub use error::Error;
+pub use replay::Recipe;
+pub use batch::BatchInput;
@@ -1,7 +1,18 @@
-use std::fs::{OpenOptions, copy, rename, remove_file};
-use memmap::MmapMut;
+use std::fs::{OpenOptions, copy, rename, remove_file, write};
+use memmap::{Mmap, MmapMut};
 use std::path::PathBuf;
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+/// The path standing for stdin as an input, or stdout as an output.
+pub const STREAM: &str = "-";
+
+lazy_static! {
+    /// Everything read from stdin, which can only be read once.
+    static ref STDIN: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+}
 
 /// A Loader struct to facilitate file manipulation (loading, memorymapping...)
 pub struct Loader;
@@ -26,12 +37,58 @@ impl Loader {
         Ok(mmap)
     }
 
+    /// Constructs a read-only memory map of file at `name`.
+    pub fn map_file(name: &str) -> std::io::Result<Mmap> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(name)?;
+
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        Ok(mmap)
+    }
+
     /// A combination of `copy_file` and `map_file_mut`.
     pub fn init_file_mut(from: &str, to:&str) -> std::io::Result<memmap::MmapMut> {
-        Loader::copy_file(from, to.clone())?;
+        Loader::copy_file(from, to)?;
         Loader::map_file_mut(to)
     }
 
+    /// Writes `data` to a file at `to`, replacing it if it exists.
+    pub fn write_file(to: &str, data: &[u8]) -> std::io::Result<()> {
+        write(to, data)
+    }
+
+    /// Reads the whole file at `name`, or stdin if it's `-`.
+    /// 
+    /// Stdin is read once, then kept in memory for every later call.
+    pub fn read_file(name: &str) -> std::io::Result<Vec<u8>> {
+        if name != STREAM {
+            return std::fs::read(name);
+        }
+
+        let mut stdin = STDIN.lock().map_err(|_| Error::other("Couldn't lock stdin."))?;
+        if stdin.is_none() {
+            let mut data = Vec::new();
+            std::io::stdin().lock().read_to_end(&mut data)?;
+            *stdin = Some(data);
+        }
+
+        Ok(stdin.as_ref().cloned().unwrap_or_default())
+    }
+
+    /// Writes `data` to a file at `to` like `write_file`, or to stdout if it's `-`.
+    pub fn write_output(to: &str, data: &[u8]) -> std::io::Result<()> {
+        if to != STREAM {
+            return Loader::write_file(to, data);
+        }
+
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(data)?;
+        stdout.flush()
+    }
+
     pub fn rename_file(from: &str, to: &str) -> std::io::Result<()> {
         if !Loader::file_exists(from) {
             Err(Error::new(ErrorKind::NotFound, format!("File '{}' does not exist.", from)))
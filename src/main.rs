@@ -1,32 +1,144 @@
-//! A main function. Currently doesn't have anything since work on 
-//! a databender hasn't started yet.
+//! The glitchup executable. Loads the configuration from the command line
+//! and the options file, then runs the databender using the library.
 
-mod benders;
-mod mutations;
-mod loaders;
-mod configuration;
+mod cli;
 
-use benders::KaBender;
-use configuration::Configuration;
+use glitchup::{batch, registry, BatchInput, Configuration, Error, KaBender, Recipe};
+use glitchup::replay::{Failure, RunManifest};
+use cli::Cli;
 
 use rayon::prelude::*;
 
+use std::sync::Mutex;
+use std::time::Instant;
+
 fn main() {
+    // Parses the arguments passed to the application.
+    let cli = Cli::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}\n\n{}", err, cli::USAGE);
+        std::process::exit(1);
+    });
+
+    if cli.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    // Initialises the mutation registry at the start.
+    lazy_static::initialize(&registry::REGISTRY);
+
+    // Every error has already been printed, so only the exit code is left to set.
+    if let Err(err) = run(&cli) {
+        std::process::exit(err.exit_code());
+    }
+}
+
+/// Prints an error that the bender didn't report itself.
+fn report(error: Error) -> Error {
+    eprintln!("{}", error);
+    error
+}
+
+/// Runs the bender as configured, returning the first error once everything is done.
+fn run(cli: &Cli) -> Result<(), Error> {
     // Initialises the configuration for the application.
-    let conf = Configuration::from_file("Options.toml");
+    let conf = cli.configuration().map_err(report)?;
+
+    // Replays a previous output instead, if asked to.
+    if let Some(replay) = conf.get("replay").and_then(|replay| replay.as_str()) {
+        let recipe = Recipe::load(replay)
+            .map_err(|err| report(Error::Config(format!("Couldn't replay '{}': {}", replay, err))))?;
+        let conf = recipe.apply_to(&conf);
+
+        // Recipes can be edited by hand, or come from an older version, so each step is checked
+        // along with the options of every other step.
+        let steps = recipe.mutations.iter().fold(conf.clone(), |conf, step| step.to_configuration(&conf));
+        for step in &recipe.mutations {
+            step.to_configuration(&steps).verify_config().map_err(report)?;
+        }
 
-    conf.verify_config();
+        return KaBender::new(&conf, recipe.seed).map_err(report)?.replay(&recipe);
+    }
 
-    // Initialises the mutation map at the start.
-    lazy_static::initialize(&benders::MUTMAP);
+    conf.verify_config().map_err(report)?;
 
     // Retrieves some options from the configuration.
     let loops = conf.get("times")
         .and_then(|times| times.as_int())
         .unwrap_or(&1);
 
-    (0..*loops).into_par_iter().for_each(|i| {
-        let bender = KaBender::new(&conf, i.to_string());
-        bender.run();
-    });
-}
\ No newline at end of file
+    // Uses the given seed, or picks one at random so that the run can still be reproduced.
+    let seed = conf.get_seed().map_err(report)?.unwrap_or_else(rand::random);
+    eprintln!("Seed: {}", seed);
+
+    // Records every output for the manifest of the run, if one was asked for.
+    let run_manifest = conf.get("run_manifest").and_then(|path| path.as_str());
+    let records = Mutex::new(Vec::new());
+    let recording = run_manifest.map(|_| &records);
+    let started = Instant::now();
+
+    // Bends every file of a batch instead, if one was given.
+    let results = match batch::inputs(&conf).map_err(report)? {
+        Some(inputs) => run_batch(&conf, &inputs, *loops, seed, recording),
+        None => {
+            let input = conf.get_inputfile().map_err(report)?;
+
+            // Every time runs to the end, even if another one fails.
+            (0..*loops).into_par_iter().map(|i| {
+                let result = KaBender::new(&conf, seed.wrapping_add(i as u64)).map_err(report)
+                    .and_then(|bender| recorded(bender.with_run(i as usize), recording).run());
+                (input.to_string(), result)
+            }).collect()
+        },
+    };
+
+    if let Some(path) = run_manifest {
+        let mut outputs = records.into_inner().unwrap_or_default();
+        outputs.sort_by(|a, b| a.output.cmp(&b.output));
+
+        let failures = results.iter()
+            .filter_map(|(input, result)| result.as_ref().err().map(|err| Failure { input: input.clone(), error: err.to_string() }))
+            .collect();
+
+        let manifest = RunManifest { seed, millis: started.elapsed().as_secs_f64() * 1000.0, outputs, failures };
+        manifest.save(path).map_err(|err| report(Error::io(path, err)))?;
+    }
+
+    results.into_iter().try_for_each(|(_, result)| result)
+}
+
+/// Adds the outputs of the bender to `records`, if there are any.
+fn recorded<'a>(bender: KaBender<'a>, records: Option<&'a Mutex<Vec<Recipe>>>) -> KaBender<'a> {
+    match records {
+        Some(records) => bender.with_records(records),
+        None => bender,
+    }
+}
+
+/// Bends every file of a batch in parallel, each with the same seed, then sums up which ones failed.
+/// 
+/// Returns the result of each file, along with its path.
+fn run_batch(conf: &Configuration, inputs: &[BatchInput], loops: i64, seed: u64, records: Option<&Mutex<Vec<Recipe>>>) -> Vec<(String, Result<(), Error>)> {
+    let results: Vec<(String, Result<(), Error>)> = inputs.par_iter().map(|input| {
+        let result = input.configure(conf).and_then(|conf| {
+            let results: Vec<Result<(), Error>> = (0..loops).into_par_iter().map(|i| {
+                recorded(KaBender::new(&conf, seed.wrapping_add(i as u64))?.with_run(i as usize), records).run()
+            }).collect();
+
+            results.into_iter().collect()
+        });
+
+        (input.path.clone(), result)
+    }).collect();
+
+    let failed: Vec<(&String, &Error)> = results.iter()
+        .filter_map(|(input, result)| result.as_ref().err().map(|err| (input, err)))
+        .collect();
+
+    eprintln!("\nBent {} of {} files.", inputs.len() - failed.len(), inputs.len());
+    for (input, err) in &failed {
+        eprintln!("Failed '{}': {}", input, err);
+    }
+
+    results
+}
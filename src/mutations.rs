@@ -1,6 +1,6 @@
 use rand::Rng;
 use rand_xorshift::XorShiftRng;
-use rand_core::{SeedableRng, RngCore};
+use rand_core::RngCore;
 use rand::seq::SliceRandom;
 
 use rayon::iter::IntoParallelRefMutIterator;
@@ -8,93 +8,211 @@ use rayon::iter::ParallelIterator;
 
 use moveslice::Moveslice;
 
-use std::sync::{Arc, Mutex};
+use crate::error::Error;
+use crate::targeting::{Target, Regions};
 
-fn index_boundary(data: &[u8]) -> (usize, usize) {
-    (data.len()/50, data.len())
+/// The random number generator every mutation draws from.
+/// 
+/// Seeded once per mutation list, which makes a run reproducible from its seed.
+pub type MutRng = XorShiftRng;
+
+/// The bounds mutations pick indices from by default, both being multiples of `align`.
+pub(crate) fn index_boundary(len: usize, align: usize) -> (usize, usize) {
+    let min = align_down(len/50, align);
+    (min, min + align_down(len - min, align))
+}
+
+pub(crate) fn align_down(value: usize, align: usize) -> usize {
+    value - value % align
+}
+
+pub(crate) fn align_up(value: usize, align: usize) -> usize {
+    align_down(value + align - 1, align)
+}
+
+/// Reads an option that must be a string, using `default` if it isn't specified.
+fn get_text(cfg: &crate::Configuration, name: &str, option: &str, default: &str) -> Result<String, Error> {
+    match cfg.get_option(name, option) {
+        Some(value) => value.as_str().cloned().ok_or_else(|| Error::invalid_option(name, option, "a string")),
+        None => Ok(default.into()),
+    }
+}
+
+/// Reads an option that must be a float or range, using `default` if it isn't specified.
+/// 
+/// Like `get_opt_as!`, an option that's specified but invalid is an error, and a number is drawn from `rng` either way.
+fn get_float_or(cfg: &crate::Configuration, rng: &mut MutRng, name: &str, option: &str, default: f64) -> Result<f64, Error> {
+    match cfg.generate_float_from_option(name, option, rng) {
+        Some(value) => Ok(value),
+        None if cfg.get_option(name, option).is_none() => Ok(default),
+        None => Err(Error::invalid_option(name, option, "a valid float, or range")),
+    }
+}
+
+/// Reads the `align` option: the number of bytes treated as a single unit, such as an audio frame.
+/// 
+/// Defaults to 1. Unlike other options, it never draws from the RNG.
+fn get_align(cfg: &crate::Configuration, name: &str) -> usize {
+    cfg.get_option(name, "align")
+        .and_then(|align| align.as_int())
+        .map_or(1, |align| (*align).max(1) as usize)
 }
 
-fn generate_index(data: &[u8]) -> usize {
-    let (min, max) = index_boundary(data);
-    rand::thread_rng().gen_range(min, max)
+/// The regions set by `range` and `exclude`, or `None` if neither is set.
+/// 
+/// Used by mutations that don't pick positions themselves, which can touch the whole data by default.
+fn explicit_regions(cfg: &crate::Configuration, name: &str, len: usize, align: usize) -> Result<Option<Regions>, Error> {
+    let target = Target::from_config(cfg, name)?;
+    Ok(if target.is_default() { None } else { Some(target.regions(len, align)) })
 }
 
 macro_rules! get_opt_as {
-    (int, $cfg:ident, $configname:tt, $value:tt, $type:ty) => {
+    (int, $cfg:ident, $rng:ident, $configname:tt, $value:tt, $type:ty) => {
         {
-             $cfg.generate_int_from_option($configname, $value)
+             $cfg.generate_int_from_option($configname, $value, $rng)
                 .map(|option| option as $type)
-                .ok_or(MutationError::new(
-                    format!("Expected '{}' to be under '{} or globally as a valid integer, or range.", $value, $configname)
-                ))
+                .ok_or_else(|| Error::invalid_option($configname, $value, "a valid integer, or range"))
         }
     };
-    (float, $cfg:ident, $configname:tt, $value:tt, $type:ty) => {
+    (float, $cfg:ident, $rng:ident, $configname:tt, $value:tt, $type:ty) => {
         {
-            $cfg.generate_float_from_option($configname, $value)
+            $cfg.generate_float_from_option($configname, $value, $rng)
                .map(|option| option as $type)
-               .ok_or(MutationError::new(
-                   format!("Expected '{}' to be under '{} or globally as a valid float, or range.", $configname, $value)
-               ))
+               .ok_or_else(|| Error::invalid_option($configname, $value, "a valid float, or range"))
        }
     };
 }
 
 macro_rules! index_range {
-    ($data:ident, $chunksize:ident) => {
+    ($data:ident, $target:expr, $chunksize:ident, $rng:ident) => {
+        index_range!($data, $target, $chunksize, $rng, 1)
+    };
+    ($data:ident, $target:expr, $chunksize:ident, $rng:ident, $align:expr) => {
         {
-            let len = $data.len();
-            let start = generate_index($data);
-            let end = if $chunksize + start > len {len} else {$chunksize + start};
-            start..end
+            let regions = $target.regions($data.len(), $align);
+            let start = regions.pick($rng, $align);
+            regions.chunk(start, align_up($chunksize, $align))
         }
     };
 }
 
 macro_rules! slice_mut {
-    ($data:ident, $chunksize:ident) => {
-        $data.get_mut(index_range!($data, $chunksize))
+    ($data:ident, $target:expr, $chunksize:ident, $rng:ident) => {
+        slice_mut!($data, $target, $chunksize, $rng, 1)
+    };
+    ($data:ident, $target:expr, $chunksize:ident, $rng:ident, $align:expr) => {
+        $data.get_mut(index_range!($data, $target, $chunksize, $rng, $align))
     };
 }
 
-pub struct MutationError {
-    pub error: String
+/// The type of value an option expects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionKind {
+    Int,
+    Float,
+    /// A string, which can't contain `_`.
+    Text,
 }
 
-impl MutationError {
-    fn new(error: String) -> Self {
-        MutationError { error }
+/// An option read by a mutation.
+/// 
+/// Built with `OptionSpec::new`, as more fields can be added.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct OptionSpec {
+    /// The name of the option, such as `chunksize`.
+    pub name: &'static str,
+    /// The short name used in the mutation's log, such as `ch`.
+    pub short: &'static str,
+    pub kind: OptionKind,
+    /// The value used when the option is specified neither under the mutation's section nor globally.
+    pub default: Option<toml::Value>,
+    /// The lowest value allowed, checked before any work starts.
+    pub min: Option<f64>,
+    /// Whether the option must be specified, if it has no default.
+    pub required: bool,
+}
+
+impl OptionSpec {
+    /// A required option, with no default and no lowest value.
+    pub fn new(name: &'static str, short: &'static str, kind: OptionKind) -> Self {
+        OptionSpec { name, short, kind, default: None, min: None, required: true }
+    }
+
+    pub fn with_default<V: Into<toml::Value>>(mut self, default: V) -> Self {
+        self.default = Some(default.into());
+        self
+    }
+
+    pub fn at_least(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    /// Marks the option as optional, for mutations that fall back to a value of their own.
+    pub fn optional(mut self) -> Self {
+        self.required = false;
+        self
     }
 }
 
-pub fn chaos(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
-    let name = "ChaosConfig";
+/// A mutation that can be used in the `mutations` option.
+/// 
+/// For an output to be replayable, `apply` should return a log of the form
+/// `CODE_short=value_short=value...`, containing every option it declares.
+pub trait Mutation: Send + Sync {
+    /// The name used in the `mutations` option, such as `Shift`.
+    /// Its options are read from `<name>Config`.
+    fn name(&self) -> &str;
+
+    /// The short code starting the mutation's log, such as `SFT`.
+    fn code(&self) -> &str;
+
+    /// Every option the mutation reads.
+    fn options(&self) -> Vec<OptionSpec>;
+
+    /// Mutates `data`, returning a log of what was done.
+    fn apply(&self, data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error>;
+
+    /// Whether the mutation changes the size of the data.
+    /// 
+    /// If so, the bender keeps the data in memory rather than memory-mapping it, and uses `apply_resizable`.
+    fn resizes(&self) -> bool {
+        false
+    }
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
+    /// Mutates `data`, which is allowed to grow or shrink. Calls `apply` by default.
+    fn apply_resizable(&self, data: &mut Vec<u8>, cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+        self.apply(data, cfg, rng)
+    }
+}
 
-    let xrng = Arc::new(Mutex::new(XorShiftRng::from_rng(rand::thread_rng())
-        .map_err(|err| MutationError::new(err.to_string()))?));
+pub fn chaos(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "ChaosConfig";
 
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
 
     for _ in 0..iterations {
-        if let Some(slice) = slice_mut!(data, chunksize) {
-            slice.par_iter_mut().for_each(|chr| *chr = xrng.lock().unwrap().next_u32() as u8);
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
+            rng.fill_bytes(slice);
         }
     }
 
     Ok(format!("CHS_it={}_ch={}", iterations, chunksize))
 }
 
-pub fn compress(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn compress(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "CompressConfig";
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
-    let compress_by = get_opt_as!(int, cfg, name, "compress_by", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let compress_by = get_opt_as!(int, cfg, rng, name, "compress_by", usize)?;
 
     for _ in 0..iterations {
-        if let Some(slice) = slice_mut!(data, chunksize) {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
             let mut c_index = 0; // index of byte to use
 
                 let mut sclone = vec![0; slice.len()];
@@ -112,22 +230,23 @@ pub fn compress(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, M
         }
     }
 
-    Ok(format!("CMP_it={}_ch={}", iterations, chunksize))
+    Ok(format!("CMP_it={}_ch={}_by={}", iterations, chunksize, compress_by))
 }
 
-pub fn gradient(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn gradient(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "GradientConfig";
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
-    let accelerate_by = get_opt_as!(int, cfg, name, "accelerate_by", usize)?;
-    let accelerate_in = get_opt_as!(int, cfg, name, "accelerate_in", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let accelerate_by = get_opt_as!(int, cfg, rng, name, "accelerate_by", usize)?;
+    let accelerate_in = get_opt_as!(int, cfg, rng, name, "accelerate_in", usize)?;
 
     for _ in 0..iterations {
         let mut n = accelerate_by;
         let mut i = 0;
 
-        if let Some(slice) = slice_mut!(data, chunksize) {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
             for chr in slice.iter_mut() {
                 *chr = ((*chr as usize + n) % 256) as u8;
                 i += 1;
@@ -144,15 +263,16 @@ pub fn gradient(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, M
         iterations, chunksize, accelerate_by, accelerate_in))
 }
 
-pub fn increase(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn increase(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "IncreaseConfig";
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
-    let increase_by = get_opt_as!(int, cfg, name, "increase_by", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let increase_by = get_opt_as!(int, cfg, rng, name, "increase_by", usize)?;
 
     for _ in 0..iterations {
-        if let Some(slice) = slice_mut!(data, chunksize) {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
             for chr in slice.iter_mut() {
                 *chr = ((*chr as usize + increase_by) % 256) as u8;
             }
@@ -163,17 +283,25 @@ pub fn increase(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, M
         iterations, chunksize, increase_by))
 }
 
-pub fn loops(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn loops(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "LoopsConfig";
 
     // Options
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
-    let mut loops = get_opt_as!(int, cfg, name, "loops", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let mut loops = get_opt_as!(int, cfg, rng, name, "loops", usize)?;
 
-    // Extra variables needed
-    let (index_min, index_max) = index_boundary(data);
-    let len = data.len();
+    // Loops whole frames when aligned.
+    let align = get_align(cfg, name);
+    let chunksize = align_up(chunksize, align);
+
+    if chunksize == 0 {
+        return Err(Error::invalid_option(name, "chunksize", "a positive integer"));
+    }
+
+    let target = Target::from_config(cfg, name)?;
+    let regions = target.regions(data.len(), align);
+    regions.require_room(data.len(), chunksize, align, "Loops")?;
 
     // Update loops
     // Below is formula explaining why this code is needed.
@@ -182,12 +310,19 @@ pub fn loops(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, Muta
     // MIN < MAX-(CH*LP)
     // CH*LP < MAX-MIN
     // LP < (MAX-MIN)/CH
-    let min_safe_loops = (index_max-index_min)/chunksize;
+    let min_safe_loops = (regions.largest()-1)/chunksize;
     loops = loops.min(min_safe_loops);
 
     for _ in 0..iterations {
-        let index = rand::thread_rng()
-            .gen_range(index_min, index_max-(chunksize*loops));
+        // Loops within a single region, so that nothing outside of the target is touched.
+        let (index_min, index_max) = match regions.pick_region(chunksize, rng) {
+            Some(region) => (region.start, region.end),
+            None => break,
+        };
+        let loops = loops.min((index_max-index_min-1)/chunksize);
+
+        let within = index_min..index_max-(chunksize*loops);
+        let index = align_down(regions.drift(rng.gen_range(within.start, within.end), within), align);
 
             // Get whole file to allow circular access
             if let Some(slice) = data.get_mut(0..) {
@@ -204,17 +339,8 @@ pub fn loops(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, Muta
                                 ((index + chunksize * rep) % (index_max-index_min)) + index_min
                             };
 
-                        // Shows important info before panic - for catching bugs.
-                        if index > len || modind > len {
-                            eprintln!("Diagnostics before panic.");
-                            eprintln!("index:{}, min/max:{}/{}, modind:{}, chsize:{}, in+ch+tp:{} % max {} + min {}",
-                             index, index_min, index_max, modind, chunksize, index + chunksize * rep,
-                             (index + chunksize * rep) % index_max, ((index + chunksize * rep) % index_max) + index_min);
-                            panic!("Out of bounds error. If you see this, please contact the developer.");
-                        }
-                            
-                        // "Repeat" current byte across other byte.
-                        slice[modind] = slice[index];
+                        // "Repeat" current byte (or frame) across other byte.
+                        slice.copy_within(index..index + align, modind);
                     }
                 }
             }
@@ -224,15 +350,16 @@ pub fn loops(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, Muta
         iterations, chunksize, loops))
 }
 
-pub fn multiply(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn multiply(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "MultiplyConfig";
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
-    let multiply_by = get_opt_as!(float, cfg, name, "multiply_by", f64)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let multiply_by = get_opt_as!(float, cfg, rng, name, "multiply_by", f64)?;
 
     for _ in 0..iterations {
-        if let Some(slice) = slice_mut!(data, chunksize) {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
             for chr in slice.iter_mut() {
                 *chr = ((*chr as f64 * multiply_by) as usize % 256) as u8;
             }
@@ -243,15 +370,22 @@ pub fn multiply(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, M
         iterations, chunksize, multiply_by))
 }
 
-pub fn reverse(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn reverse(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "ReverseConfig";
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let align = get_align(cfg, name);
 
     for _ in 0..iterations {
-        if let Some(slice) = slice_mut!(data, chunksize) {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng, align) {
             slice.reverse();
+
+            // Reverses the order of frames, rather than the bytes within them.
+            if align > 1 {
+                slice.chunks_exact_mut(align).for_each(|frame| frame.reverse());
+            }
         }
     }
 
@@ -259,20 +393,30 @@ pub fn reverse(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, Mu
         iterations, chunksize))
 }
 
-pub fn shift(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn shift(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "ShiftConfig";
 
-    let (index_min, index_max) = index_boundary(data);
-    let new_max = index_max - index_min;
+    let align = get_align(cfg, name);
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = align_up(get_opt_as!(int, cfg, rng, name, "chunksize", usize)?, align);
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let regions = target.regions(data.len(), align);
+    regions.require_room(data.len(), chunksize, align, "Shift")?;
 
     for _ in 0..iterations {
-        let index = rand::thread_rng().gen_range(0, new_max);
-        let m_index = rand::thread_rng().gen_range(0, new_max - chunksize);
+        // Shifts within a single region, so that nothing outside of the target is touched.
+        let (index_min, index_max) = match regions.pick_region(chunksize, rng) {
+            Some(region) => (region.start, region.end),
+            None => break,
+        };
+        let new_max = index_max - index_min;
 
-        if let Some(mut slice) = data.get_mut(index_min..index_max) {
+        let index = align_down(regions.drift(rng.gen_range(0, new_max), 0..new_max), align);
+        let m_index = align_down(regions.drift(rng.gen_range(0, new_max - chunksize), 0..new_max - chunksize), align);
+
+        if let Some(slice) = data.get_mut(index_min..index_max) {
             let max_i = if chunksize+index > slice.len() {slice.len()} else {chunksize+index};
                 let result = slice.try_moveslice(
                     index..max_i,
@@ -289,15 +433,16 @@ pub fn shift(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, Muta
         iterations, chunksize))
 }
 
-pub fn shuffle(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn shuffle(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "ShuffleConfig";
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
 
     for _ in 0..iterations {
-        if let Some(slice) = slice_mut!(data, chunksize) {
-            slice.shuffle(&mut rand::thread_rng());
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
+            slice.shuffle(rng);
         }
     }
 
@@ -305,61 +450,69 @@ pub fn shuffle(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, Mu
         iterations, chunksize))
 }
 
-pub fn swap(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn swap(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "SwapConfig";
 
     // Options
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
 
-    // Validation check
-    if chunksize >= (0.49 * data.len() as f64) as usize {
-        println!();
-        return Err(MutationError::new("Cannot perform Swap - Chunksize is too large.".into()));
-    }
+    // Swaps whole frames when aligned.
+    let align = get_align(cfg, name);
+    let chunksize = align_up(chunksize, align);
 
     // Extra variables needed
-    let mut rng = rand::thread_rng();
-    let (index_min, index_max) = index_boundary(data);
-    let sl = data.get_mut(index_min..index_max).unwrap();
-    let len = sl.len();
+    let target = Target::from_config(cfg, name)?;
+    let regions = target.regions(data.len(), align);
+
+    // Validation check
+    if target.is_default() && (chunksize >= (0.49 * data.len() as f64) as usize || regions.largest() <= 2 * chunksize) {
+        let needed = ((chunksize as f64 / 0.49) as usize + 1).max(2 * chunksize + chunksize / 49 + align);
+        return Err(Error::DataTooSmall { mutation: "Swap".into(), len: data.len(), needed });
+    }
+    regions.require_room(data.len(), 2 * chunksize, align, "Swap")?;
 
     // Actual mutation
     for _ in 0..iterations {
-        let splitdex = rng.gen_range(chunksize, (index_max-index_min)-chunksize);
+        // Swaps within a single region, so that nothing outside of the target is touched.
+        let (index_min, index_max) = match regions.pick_region(2 * chunksize, rng) {
+            Some(region) => (region.start, region.end),
+            None => break,
+        };
 
-        let (left, right) = sl.split_at_mut(splitdex);
+        let sl = &mut data[index_min..index_max];
+        let len = sl.len();
+
+        let splitdex = align_down(rng.gen_range(chunksize, (index_max-index_min)-chunksize), align);
 
-        let index1 = rng.gen_range(0, splitdex - chunksize);
-        let index2 = rng.gen_range(0, len - splitdex - chunksize);
-        let (llen, rlen) = {(left.len(), right.len())};
+        let (left, right) = sl.split_at_mut(splitdex);
 
-        let slice1 = left.get_mut(index1..index1+chunksize);
-        let slice2 = right.get_mut(index2..index2+chunksize);
+        // The split can land right after the first chunk, leaving it a single place to be.
+        let index1 = if splitdex > chunksize {
+            align_down(regions.drift(rng.gen_range(0, splitdex - chunksize), 0..splitdex - chunksize), align)
+        } else {
+            0
+        };
+        let index2 = align_down(regions.drift(rng.gen_range(0, len - splitdex - chunksize), 0..len - splitdex - chunksize), align);
 
-        if slice1.is_none() || slice2.is_none() {
-            eprintln!("Diagnostics before panic.");
-            eprintln!("i1r:{}, i2r:{}", splitdex - chunksize, len - splitdex - chunksize);
-            eprintln!("flen:{}, dex:{}, ch:{}", len, splitdex, chunksize);
-            eprintln!("len:{}, i1a/i1b:{}/{}", llen, index1, index1+chunksize);
-            eprintln!("len:{}, i2a/i2b:{}/{}", rlen, index2, index2+chunksize);
-            panic!("Out of bounds error. If you see this, please contact the developer.");
+        if let (Some(slice1), Some(slice2)) = (left.get_mut(index1..index1+chunksize), right.get_mut(index2..index2+chunksize)) {
+            slice1.swap_with_slice(slice2);
         }
-        slice1.unwrap().swap_with_slice(slice2.unwrap());
     }
 
     Ok(format!("SWP_it={}_ch={}",
         iterations, chunksize))
 }
 
-pub fn void(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, MutationError> {
+pub fn void(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
     let name = "VoidConfig";
 
-    let iterations = get_opt_as!(int, cfg, name, "iterations", usize)?;
-    let chunksize = get_opt_as!(int, cfg, name, "chunksize", usize)?;
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
 
     for _ in 0..iterations {
-        if let Some(slice) = slice_mut!(data, chunksize) {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
             for chr in slice.iter_mut() {
                 *chr = b'0';
             }
@@ -369,4 +522,1131 @@ pub fn void(data: &mut [u8], cfg: &crate::Configuration) -> Result<String, Mutat
 
     Ok(format!("VOID_it={}_ch={}",
         iterations, chunksize))
-}
\ No newline at end of file
+}
+/* Bit-level mutations */
+
+pub fn bitflip(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "BitFlipConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let probability = get_opt_as!(float, cfg, rng, name, "probability", f64)?;
+
+    // The number of bits skipped before the next flip, so that low probabilities don't draw once for every bit.
+    let skip = |rng: &mut MutRng, bits: usize| -> usize {
+        if probability >= 1.0 {
+            0
+        } else if probability <= 0.0 {
+            bits
+        } else {
+            ((1.0 - rng.gen::<f64>()).ln() / (1.0 - probability).ln()).min(bits as f64) as usize
+        }
+    };
+
+    for _ in 0..iterations {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
+            let bits = slice.len() * 8;
+            let mut bit = skip(rng, bits);
+
+            while bit < bits {
+                slice[bit / 8] ^= 1 << (bit % 8);
+                bit += 1 + skip(rng, bits);
+            }
+        }
+    }
+
+    Ok(format!("BFL_it={}_ch={}_p={}",
+        iterations, chunksize, probability))
+}
+
+pub fn bitrotate(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "BitRotateConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let rotate_by = get_opt_as!(int, cfg, rng, name, "rotate_by", i64)?;
+
+    for _ in 0..iterations {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
+            for chr in slice.iter_mut() {
+                *chr = chr.rotate_left(rotate_by.rem_euclid(8) as u32);
+            }
+        }
+    }
+
+    Ok(format!("BRT_it={}_ch={}_by={}",
+        iterations, chunksize, rotate_by))
+}
+
+pub fn bitshift(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "BitShiftConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let shift_by = get_opt_as!(int, cfg, rng, name, "shift_by", i64)?;
+
+    // Positive values shift towards the start of the data, negative ones towards the end.
+    let bytes = (shift_by.unsigned_abs() / 8) as usize;
+    let bits = (shift_by.unsigned_abs() % 8) as u32;
+
+    for _ in 0..iterations {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
+            if shift_by < 0 {
+                slice.reverse();
+            }
+
+            // Shifts the whole chunk as one long string of bits, filling the end with zeros.
+            let len = slice.len();
+            for i in 0..len {
+                let high = if i + bytes < len { slice[i + bytes] } else { 0 };
+                let low = if i + bytes + 1 < len { slice[i + bytes + 1] } else { 0 };
+
+                slice[i] = if bits == 0 {
+                    high
+                } else if shift_by < 0 {
+                    (high >> bits) | (low << (8 - bits))
+                } else {
+                    (high << bits) | (low >> (8 - bits))
+                };
+            }
+
+            if shift_by < 0 {
+                slice.reverse();
+            }
+        }
+    }
+
+    Ok(format!("BSH_it={}_ch={}_by={}",
+        iterations, chunksize, shift_by))
+}
+
+/// Parses a key written in hexadecimal, such as `deadbeef` or `0xDEADBEEF`.
+fn parse_hex(key: &str) -> Option<Vec<u8>> {
+    let key = key.trim_start_matches("0x");
+
+    if !key.len().is_multiple_of(2) || !key.is_ascii() {
+        return None;
+    }
+
+    (0..key.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&key[index..index + 2], 16).ok())
+        .collect()
+}
+
+pub fn xor(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "XorConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+
+    // The key is either written in hexadecimal as `key`, or read from `key_file`.
+    // Since a file can't be embedded in the log, its key is logged as `file`.
+    let (key, logged) = match cfg.get_option(name, "key_file").and_then(|file| file.as_str()) {
+        Some(file) => {
+            let key = std::fs::read(file)
+                .map_err(|err| Error::io(file, err))?;
+            (key, "file".to_string())
+        },
+        None => {
+            let hex = cfg.get_option(name, "key").and_then(|key| key.as_str())
+                .ok_or_else(|| Error::invalid_option(name, "key", "a hexadecimal string, or 'key_file' as a path"))?;
+            let key = parse_hex(hex)
+                .ok_or_else(|| Error::invalid_option(name, "key", format!("a hexadecimal string, not '{}'", hex)))?;
+            (key, hex.to_lowercase())
+        },
+    };
+
+    if key.is_empty() {
+        return Err(Error::invalid_option(name, "key", "a key that isn't empty"));
+    }
+
+    for _ in 0..iterations {
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng) {
+            slice.iter_mut()
+                .zip(key.iter().cycle())
+                .for_each(|(chr, key)| *chr ^= key);
+        }
+    }
+
+    Ok(format!("XOR_it={}_ch={}_key={}",
+        iterations, chunksize, logged))
+}
+
+/* Mutations that change the size of the data */
+
+pub fn delete(data: &mut Vec<u8>, cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "DeleteConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let align = get_align(cfg, name);
+
+    for _ in 0..iterations {
+        if data.is_empty() {
+            break;
+        }
+
+        let bytes: &[u8] = data;
+        let range = index_range!(bytes, target, chunksize, rng, align);
+        data.drain(range);
+    }
+
+    Ok(format!("DEL_it={}_ch={}",
+        iterations, chunksize))
+}
+
+pub fn duplicate(data: &mut Vec<u8>, cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "DuplicateConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let align = get_align(cfg, name);
+
+    for _ in 0..iterations {
+        if data.is_empty() {
+            break;
+        }
+
+        // Repeats the chunk right after itself.
+        let bytes: &[u8] = data;
+        let range = index_range!(bytes, target, chunksize, rng, align);
+        let chunk = data[range.clone()].to_vec();
+        data.splice(range.end..range.end, chunk);
+    }
+
+    Ok(format!("DUP_it={}_ch={}",
+        iterations, chunksize))
+}
+
+pub fn insert(data: &mut Vec<u8>, cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "InsertConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let align = get_align(cfg, name);
+    let target = Target::from_config(cfg, name)?;
+
+    // Either inserts random bytes, or repeats the byte (or frame) found where the chunk is inserted.
+    let fill = get_text(cfg, name, "fill", "random")?;
+
+    if fill != "random" && fill != "repeat" {
+        return Err(Error::invalid_option(name, "fill", format!("'random' or 'repeat', not '{}'", fill)));
+    }
+
+    for _ in 0..iterations {
+        if data.is_empty() {
+            break;
+        }
+
+        let regions = target.regions(data.len(), align);
+        if regions.is_empty() {
+            break;
+        }
+
+        let index = regions.pick(rng, align);
+        let mut chunk = vec![0; align_up(chunksize, align)];
+
+        if fill == "random" {
+            rng.fill_bytes(&mut chunk);
+        } else {
+            let frame = &data[index..(index + align).min(data.len())];
+            chunk.iter_mut().zip(frame.iter().cycle()).for_each(|(chr, byte)| *chr = *byte);
+        }
+
+        data.splice(index..index, chunk);
+    }
+
+    Ok(format!("INS_it={}_ch={}_fill={}",
+        iterations, chunksize, fill))
+}
+
+pub fn truncate(data: &mut Vec<u8>, cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "TruncateConfig";
+
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let align = get_align(cfg, name);
+    let target = Target::from_config(cfg, name)?;
+
+    // The cut lands within the last region, which never includes the start of the data unless `range` does.
+    if let Some(region) = target.regions(data.len(), align).last() {
+        let len = region.end.saturating_sub(align_up(chunksize, align)).max(region.start);
+
+        // Everything after the cut is gone, so it can't hold anything excluded.
+        if target.excluded(data.len()).any(|span| span.end > len) {
+            return Err(Error::invalid_option(name, "exclude",
+                format!("spans ending before byte {}, where Truncate cuts the data", len)));
+        }
+
+        data.truncate(len);
+    }
+
+    Ok(format!("TRC_ch={}",
+        chunksize))
+}
+
+/* Mutations reading from other files */
+
+/// Reads the `donors` option, being either a single path or a list of them.
+fn get_donors(cfg: &crate::Configuration, name: &str) -> Result<Vec<String>, Error> {
+    let error = || Error::invalid_option(name, "donors", "a path or a list of paths");
+
+    let option = cfg.get_option(name, "donors").ok_or_else(error)?;
+
+    let donors: Vec<String> = match option.as_str() {
+        Some(donor) => vec![donor.clone()],
+        None => option.as_list().ok_or_else(error)?
+            .iter()
+            .map(|donor| donor.as_str().cloned().ok_or_else(error))
+            .collect::<Result<_, _>>()?,
+    };
+
+    if donors.is_empty() {
+        return Err(error());
+    }
+
+    Ok(donors)
+}
+
+pub fn splice(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "SpliceConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let target = Target::from_config(cfg, name)?;
+    let align = get_align(cfg, name);
+
+    // Donors are only ever read, so they're mapped rather than loaded.
+    let donors = get_donors(cfg, name)?.iter()
+        .map(|donor| crate::loaders::Loader::map_file(donor)
+            .map_err(|err| Error::io(donor, err)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for _ in 0..iterations {
+        let donor: &[u8] = &donors[rng.gen_range(0, donors.len())];
+
+        if let Some(slice) = slice_mut!(data, target, chunksize, rng, align) {
+            if donor.is_empty() {
+                continue;
+            }
+
+            // Copies from anywhere in the donor, skipping its start like any other mutation would.
+            let (min, max) = index_boundary(donor.len(), align);
+            let start = if max > min { align_down(rng.gen_range(min, max), align) } else { 0 };
+            let len = slice.len().min(donor.len() - start);
+
+            slice[..len].copy_from_slice(&donor[start..start + len]);
+        }
+    }
+
+    Ok(format!("SPL_it={}_ch={}",
+        iterations, chunksize))
+}
+
+/* Datamoshing AVI video, by restructuring its frames */
+
+/// Parses `data` as an AVI, used by the mutations below.
+fn parse_avi(data: &[u8], name: &str) -> Result<crate::formats::AviFile, Error> {
+    crate::formats::AviFile::parse(data).map_err(|err| match err {
+        Error::Format { format, message } => Error::Format {
+            format,
+            message: format!("{} only works on whole AVI files, with the 'raw' format. {}", name, message),
+        },
+        err => err,
+    })
+}
+
+pub fn drop_keyframes(data: &mut Vec<u8>, cfg: &crate::Configuration, _rng: &mut MutRng) -> Result<String, Error> {
+    let regions = explicit_regions(cfg, "DropKeyframesConfig", data.len(), 1)?;
+    let mut avi = parse_avi(data, "DropKeyframes")?;
+
+    // The first keyframe is kept, as there would be nothing to draw the delta frames upon.
+    // So are keyframes outside of `range`.
+    let mut first = true;
+    avi.chunks.retain(|chunk| {
+        if !chunk.is_video() || !chunk.keyframe {
+            return true;
+        }
+
+        std::mem::replace(&mut first, false) || regions.as_ref().is_some_and(|regions| !regions.contains(chunk.offset))
+    });
+
+    *data = avi.to_bytes();
+
+    Ok("DKF".into())
+}
+
+pub fn bloom(data: &mut Vec<u8>, cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "BloomConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let repeat = get_opt_as!(int, cfg, rng, name, "repeat", usize)?;
+
+    let regions = explicit_regions(cfg, name, data.len(), 1)?;
+    let mut avi = parse_avi(data, "Bloom")?;
+
+    for _ in 0..iterations {
+        let deltas: Vec<usize> = avi.chunks.iter().enumerate()
+            .filter(|(_, chunk)| chunk.is_video() && !chunk.keyframe)
+            .filter(|(_, chunk)| regions.as_ref().is_none_or(|regions| regions.contains(chunk.offset)))
+            .map(|(index, _)| index)
+            .collect();
+
+        if deltas.is_empty() {
+            break;
+        }
+
+        // Repeating the same motion makes it smear across the picture.
+        let index = deltas[rng.gen_range(0, deltas.len())];
+        let frame = avi.chunks[index].clone();
+        avi.chunks.splice(index + 1..index + 1, std::iter::repeat_n(frame, repeat));
+    }
+
+    *data = avi.to_bytes();
+
+    Ok(format!("BLM_it={}_rep={}",
+        iterations, repeat))
+}
+
+pub fn frame_splice(data: &mut Vec<u8>, cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "FrameSpliceConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let frames = get_opt_as!(int, cfg, rng, name, "frames", usize)?;
+
+    let donor = get_text(cfg, name, "donor", "")?;
+    if donor.is_empty() {
+        return Err(Error::invalid_option(name, "donor", "the path of an AVI"));
+    }
+
+    let donor_data = crate::loaders::Loader::map_file(&donor)
+        .map_err(|err| Error::io(&donor, err))?;
+
+    let regions = explicit_regions(cfg, name, data.len(), 1)?;
+    let mut avi = parse_avi(data, "FrameSplice")?;
+    let donor_avi = parse_avi(&donor_data, "FrameSplice")?;
+
+    // Frames can only be decoded on top of a picture of the same size and codec.
+    if avi.video_format() != donor_avi.video_format() {
+        return Err(Error::invalid_option(name, "donor", format!("an AVI with the same video format as the input, unlike '{}'", donor)));
+    }
+
+    let id = avi.chunks.iter().find(|chunk| chunk.is_video()).map(|chunk| chunk.id);
+    let deltas: Vec<&crate::formats::AviChunk> = donor_avi.chunks.iter()
+        .filter(|chunk| chunk.is_video() && !chunk.keyframe)
+        .collect();
+
+    if let Some(id) = id {
+        for _ in 0..iterations {
+            if deltas.is_empty() {
+                break;
+            }
+
+            let start = rng.gen_range(0, deltas.len().saturating_sub(frames) + 1);
+
+            // Frames are only spliced after frames within `range`.
+            let videos: Vec<usize> = avi.chunks.iter().enumerate()
+                .filter(|(_, chunk)| chunk.is_video())
+                .filter(|(_, chunk)| regions.as_ref().is_none_or(|regions| regions.contains(chunk.offset)))
+                .map(|(index, _)| index)
+                .collect();
+
+            if videos.is_empty() {
+                break;
+            }
+
+            let index = videos[rng.gen_range(0, videos.len())] + 1;
+
+            // Spliced frames count as being where they're spliced, for later iterations.
+            let offset = avi.chunks[index - 1].offset;
+            let spliced = deltas[start..(start + frames).min(deltas.len())].iter()
+                .map(|chunk| crate::formats::AviChunk { id, offset, ..(*chunk).clone() });
+
+            avi.chunks.splice(index..index, spliced);
+        }
+    }
+
+    *data = avi.to_bytes();
+
+    Ok(format!("FSP_it={}_fr={}",
+        iterations, frames))
+}
+
+/* Mutations working on pixels */
+
+/// The value pixels are sorted by, between 0 and 1.
+fn pixel_key(pixel: &[u8], order: &str, sort_by: &str) -> f64 {
+    let (r, g, b) = if pixel.len() < 3 || order.starts_with("gray") {
+        (pixel[0], pixel[0], pixel[0])
+    } else if order.starts_with("bgr") {
+        (pixel[2], pixel[1], pixel[0])
+    } else {
+        (pixel[0], pixel[1], pixel[2])
+    };
+
+    let (r, g, b) = (r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+
+    match sort_by {
+        "hue" => {
+            let delta = max - min;
+            let hue = if delta == 0.0 {
+                0.0
+            } else if max == r {
+                ((g - b) / delta).rem_euclid(6.0)
+            } else if max == g {
+                (b - r) / delta + 2.0
+            } else {
+                (r - g) / delta + 4.0
+            };
+
+            hue / 6.0
+        },
+        "saturation" => if max == 0.0 { 0.0 } else { (max - min) / max },
+        _ => 0.299 * r + 0.587 * g + 0.114 * b,
+    }
+}
+
+pub fn pixelsort(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "PixelSortConfig";
+
+    let sort_by = get_text(cfg, name, "sort_by", "luminance")?;
+    let lower = get_float_or(cfg, rng, name, "lower", 0.25)?;
+    let upper = get_float_or(cfg, rng, name, "upper", 0.8)?;
+    let angle = get_float_or(cfg, rng, name, "angle", 0.0)?;
+
+    if !["luminance", "hue", "saturation"].contains(&sort_by.as_str()) {
+        return Err(Error::invalid_option(name, "sort_by", format!("'luminance', 'hue' or 'saturation', not '{}'", sort_by)));
+    }
+
+    // The layout of the pixels is set by formats that decode images, or by hand for raw pixels.
+    let layout = |option: &str| cfg.get_option(name, option)
+        .and_then(|value| value.as_int())
+        .filter(|value| **value > 0)
+        .map(|value| *value as usize);
+
+    let (width, channels) = match (layout("width"), layout("channels")) {
+        (Some(width), Some(channels)) => (width, channels),
+        _ => return Err(Error::Config(
+            "PixelSort needs to know where each pixel is. Use a format that decodes pixels (bmp, tiff, or png with \
+             'unfilter = true' under [PngConfig]), or set 'width' and 'channels'.".into())),
+    };
+    let order = get_text(cfg, name, "channel_order", if channels >= 3 { "rgb" } else { "gray" })?;
+    if !["gray", "graya", "rgb", "rgba", "bgr", "bgra"].contains(&order.as_str()) {
+        return Err(Error::invalid_option(name, "channel_order",
+            format!("'gray', 'graya', 'rgb', 'rgba', 'bgr' or 'bgra', not '{}'", order)));
+    }
+
+    let height = data.len() / (width * channels);
+    let source = data[..width * height * channels].to_vec();
+    let keys: Vec<f64> = source.chunks_exact(channels).map(|pixel| pixel_key(pixel, &order, &sort_by)).collect();
+
+    // Pixels outside of `range` are left in place, like those outside of the thresholds.
+    let regions = explicit_regions(cfg, name, data.len(), channels)?;
+    let excluded = |index: usize| keys[index] < lower || keys[index] > upper
+        || regions.as_ref().is_some_and(|regions| !regions.contains(index * channels));
+
+    // Groups pixels into lines running along the angle, each ordered from start to end.
+    // An angle of 0 sorts rows from left to right, while 90 sorts columns from top to bottom.
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let mut pixels: Vec<(i64, f64, usize)> = (0..width * height).map(|index| {
+        let (x, y) = ((index % width) as f64, (index / width) as f64);
+        ((y * cos - x * sin).round() as i64, x * cos + y * sin, index)
+    }).collect();
+    pixels.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal)));
+
+    let mut start = 0;
+    while start < pixels.len() {
+        let end = start + pixels[start..].iter().take_while(|pixel| pixel.0 == pixels[start].0).count();
+        let line: Vec<usize> = pixels[start..end].iter().map(|pixel| pixel.2).collect();
+
+        // Only sorts intervals of pixels whose value is within the thresholds.
+        for interval in line.split(|&index| excluded(index)) {
+            let mut sorted = interval.to_vec();
+            sorted.sort_by(|&a, &b| keys[a].partial_cmp(&keys[b]).unwrap_or(std::cmp::Ordering::Equal));
+
+            for (&to, &from) in interval.iter().zip(sorted.iter()) {
+                data[to * channels..(to + 1) * channels].copy_from_slice(&source[from * channels..(from + 1) * channels]);
+            }
+        }
+
+        start = end;
+    }
+
+    Ok(format!("PXS_by={}_lo={}_hi={}_ang={}",
+        sort_by, lower, upper, angle))
+}
+
+/* Audio effects, which read the bytes they mutate as PCM samples */
+
+/// The type of each audio sample, from the `sample_format` option.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sample {
+    U8,
+    S8,
+    U16,
+    S16,
+    S24,
+    F32,
+}
+
+impl Sample {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "u8" => Some(Sample::U8),
+            "s8" => Some(Sample::S8),
+            "u16" => Some(Sample::U16),
+            "s16" => Some(Sample::S16),
+            "s24" => Some(Sample::S24),
+            "f32" => Some(Sample::F32),
+            _ => None,
+        }
+    }
+
+    /// The size of a single sample in bytes.
+    fn width(self) -> usize {
+        match self {
+            Sample::U8 | Sample::S8 => 1,
+            Sample::U16 | Sample::S16 => 2,
+            Sample::S24 => 3,
+            Sample::F32 => 4,
+        }
+    }
+}
+
+impl std::fmt::Display for Sample {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Sample::U8 => "u8",
+            Sample::S8 => "s8",
+            Sample::U16 => "u16",
+            Sample::S16 => "s16",
+            Sample::S24 => "s24",
+            Sample::F32 => "f32",
+        })
+    }
+}
+
+/// The byte order of samples wider than a byte, from the `endianness` option.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    fn parse(text: &str) -> Option<Self> {
+        match text {
+            "little" => Some(Endianness::Little),
+            "big" => Some(Endianness::Big),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Endianness {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Endianness::Little => "little",
+            Endianness::Big => "big",
+        })
+    }
+}
+
+/// How bytes are read as audio samples, from the `sample_format` and `endianness` options.
+/// 
+/// `sample_format` is one of `u8` (default), `s8`, `u16`, `s16`, `s24` or `f32`, while `endianness`
+/// is either `little` (default) or `big`.
+struct SampleFormat {
+    format: Sample,
+    endianness: Endianness,
+    /// The `align` option, rounded up to whole samples.
+    align: usize,
+    /// The parts of the data the effect is applied to.
+    target: Target,
+}
+
+impl SampleFormat {
+    fn from_options(cfg: &crate::Configuration, name: &str) -> Result<Self, Error> {
+        let format = get_text(cfg, name, "sample_format", "u8")?;
+        let format = Sample::parse(&format).ok_or_else(|| Error::invalid_option(name, "sample_format",
+            format!("'u8', 's8', 'u16', 's16', 's24' or 'f32', not '{}'", format)))?;
+
+        let endianness = get_text(cfg, name, "endianness", "little")?;
+        let endianness = Endianness::parse(&endianness).ok_or_else(|| Error::invalid_option(name, "endianness",
+            format!("'little' or 'big', not '{}'", endianness)))?;
+
+        let align = align_up(get_align(cfg, name), format.width());
+
+        Ok(SampleFormat { format, endianness, align, target: Target::from_config(cfg, name)? })
+    }
+
+    /// Reads every sample, scaled to `[-1, 1)` unless it's a float.
+    fn read(&self, data: &[u8]) -> Vec<f64> {
+        data.chunks_exact(self.format.width()).map(|bytes| {
+            // Puts the bytes of the sample in little endian order, padded to 32 bits.
+            let mut word = [0; 4];
+            word[..bytes.len()].copy_from_slice(bytes);
+            if self.endianness == Endianness::Big {
+                word[..bytes.len()].reverse();
+            }
+            let word = u32::from_le_bytes(word);
+
+            match self.format {
+                Sample::U8 => (word as f64 - 128.0) / 128.0,
+                Sample::S8 => word as u8 as i8 as f64 / 128.0,
+                Sample::U16 => (word as f64 - 32768.0) / 32768.0,
+                Sample::S16 => word as u16 as i16 as f64 / 32768.0,
+                // Shifts the sign bit of the 24-bit sample into place.
+                Sample::S24 => ((word << 8) as i32 >> 8) as f64 / 8_388_608.0,
+                Sample::F32 => f32::from_bits(word) as f64,
+            }
+        }).collect()
+    }
+
+    /// Writes every sample back, clipping anything outside of `[-1, 1)`.
+    fn write(&self, samples: &[f64], data: &mut [u8]) {
+        for (sample, bytes) in samples.iter().zip(data.chunks_exact_mut(self.format.width())) {
+            let sample = sample.clamp(-1.0, 1.0);
+
+            let word = match self.format {
+                Sample::U8 => (sample * 128.0 + 128.0).round().min(255.0) as u32,
+                Sample::S8 => (sample * 128.0).round().min(127.0) as i8 as u8 as u32,
+                Sample::U16 => (sample * 32768.0 + 32768.0).round().min(65535.0) as u32,
+                Sample::S16 => (sample * 32768.0).round().min(32767.0) as i16 as u16 as u32,
+                Sample::S24 => ((sample * 8_388_608.0).round().min(8_388_607.0) as i32 as u32) & 0xff_ffff,
+                Sample::F32 => (sample as f32).to_bits(),
+            };
+
+            let len = bytes.len();
+            bytes.copy_from_slice(&word.to_le_bytes()[..len]);
+            if self.endianness == Endianness::Big {
+                bytes.reverse();
+            }
+        }
+    }
+
+    /// Reads the samples of random chunks, processes them, then writes them back.
+    fn process<F>(&self, data: &mut [u8], iterations: usize, chunksize: usize, rng: &mut MutRng, mut effect: F)
+        where F: FnMut(&mut Vec<f64>) {
+        for _ in 0..iterations {
+            if let Some(slice) = slice_mut!(data, self.target, chunksize, rng, self.align) {
+                let mut samples = self.read(slice);
+                effect(&mut samples);
+                self.write(&samples, slice);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "fmt={}_end={}", self.format, self.endianness)
+    }
+}
+
+/// A second order filter, as described in the Audio EQ Cookbook.
+/// 
+/// * `cutoff` - The cutoff frequency, as a fraction of the sample rate (up to 0.5).
+fn biquad(samples: &mut [f64], cutoff: f64, highpass: bool) {
+    let omega = 2.0 * std::f64::consts::PI * cutoff.clamp(1e-6, 0.499);
+    let (sin, cos) = omega.sin_cos();
+    let alpha = sin / std::f64::consts::SQRT_2;
+
+    let (b0, b1) = if highpass { ((1.0 + cos) / 2.0, -(1.0 + cos)) } else { ((1.0 - cos) / 2.0, 1.0 - cos) };
+    let (a0, a1, a2) = (1.0 + alpha, -2.0 * cos, 1.0 - alpha);
+
+    let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+
+    for sample in samples.iter_mut() {
+        let x = *sample;
+        let y = (b0 * x + b1 * x1 + b0 * x2 - a1 * y1 - a2 * y2) / a0;
+
+        x2 = x1;
+        x1 = x;
+        y2 = y1;
+        y1 = y;
+        *sample = y;
+    }
+}
+
+/// Adds `samples` delayed by `delay`, fed back by `decay`.
+fn comb(samples: &[f64], delay: usize, decay: f64) -> Vec<f64> {
+    let mut output = samples.to_vec();
+
+    for i in delay.max(1)..output.len() {
+        output[i] += decay * output[i - delay.max(1)];
+    }
+
+    output
+}
+
+/// Smears `samples` over time without changing their frequencies.
+fn allpass(samples: &mut [f64], delay: usize, gain: f64) {
+    let delay = delay.max(1);
+    let input = samples.to_vec();
+
+    for i in 0..samples.len() {
+        let (x_delayed, y_delayed) = if i >= delay { (input[i - delay], samples[i - delay]) } else { (0.0, 0.0) };
+        samples[i] = -gain * input[i] + x_delayed + gain * y_delayed;
+    }
+}
+
+pub fn distortion(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "DistortionConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let drive = get_opt_as!(float, cfg, rng, name, "drive", f64)?;
+    let format = SampleFormat::from_options(cfg, name)?;
+
+    format.process(data, iterations, chunksize, rng, |samples| {
+        for sample in samples.iter_mut() {
+            *sample = (*sample * drive).tanh();
+        }
+    });
+
+    Ok(format!("DST_it={}_ch={}_dr={}_{}",
+        iterations, chunksize, drive, format))
+}
+
+pub fn echo(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "EchoConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let delay = get_opt_as!(int, cfg, rng, name, "delay", usize)?;
+    let decay = get_opt_as!(float, cfg, rng, name, "decay", f64)?;
+    let format = SampleFormat::from_options(cfg, name)?;
+
+    format.process(data, iterations, chunksize, rng, |samples| {
+        *samples = comb(samples, delay, decay);
+    });
+
+    Ok(format!("ECH_it={}_ch={}_dl={}_dc={}_{}",
+        iterations, chunksize, delay, decay, format))
+}
+
+pub fn highpass(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "HighPassConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let cutoff = get_opt_as!(float, cfg, rng, name, "cutoff", f64)?;
+    let format = SampleFormat::from_options(cfg, name)?;
+
+    format.process(data, iterations, chunksize, rng, |samples| biquad(samples, cutoff, true));
+
+    Ok(format!("HPF_it={}_ch={}_cut={}_{}",
+        iterations, chunksize, cutoff, format))
+}
+
+pub fn lowpass(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "LowPassConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let cutoff = get_opt_as!(float, cfg, rng, name, "cutoff", f64)?;
+    let format = SampleFormat::from_options(cfg, name)?;
+
+    format.process(data, iterations, chunksize, rng, |samples| biquad(samples, cutoff, false));
+
+    Ok(format!("LPF_it={}_ch={}_cut={}_{}",
+        iterations, chunksize, cutoff, format))
+}
+
+pub fn phaser(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "PhaserConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let period = get_opt_as!(int, cfg, rng, name, "period", usize)?;
+    let depth = get_opt_as!(float, cfg, rng, name, "depth", f64)?;
+    let feedback = get_opt_as!(float, cfg, rng, name, "feedback", f64)?;
+    let format = SampleFormat::from_options(cfg, name)?;
+
+    format.process(data, iterations, chunksize, rng, |samples| {
+        // Four first order allpass stages, their frequency swept by a sine of `period` samples.
+        let mut stages = [(0.0, 0.0); 4];
+        let mut last = 0.0;
+
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let lfo = (2.0 * std::f64::consts::PI * i as f64 / period.max(1) as f64).sin() * 0.5 + 0.5;
+            let tan = (std::f64::consts::PI * (0.001 + 0.2 * depth.clamp(0.0, 1.0) * lfo)).tan();
+            let coefficient = (tan - 1.0) / (tan + 1.0);
+
+            let mut value = *sample + feedback * last;
+            for (x1, y1) in stages.iter_mut() {
+                let y = coefficient * value + *x1 - coefficient * *y1;
+                *x1 = value;
+                *y1 = y;
+                value = y;
+            }
+
+            last = value;
+            *sample = (*sample + value) / 2.0;
+        }
+    });
+
+    Ok(format!("PHS_it={}_ch={}_per={}_dp={}_fb={}_{}",
+        iterations, chunksize, period, depth, feedback, format))
+}
+
+pub fn reverb(data: &mut [u8], cfg: &crate::Configuration, rng: &mut MutRng) -> Result<String, Error> {
+    let name = "ReverbConfig";
+
+    let iterations = get_opt_as!(int, cfg, rng, name, "iterations", usize)?;
+    let chunksize = get_opt_as!(int, cfg, rng, name, "chunksize", usize)?;
+    let room_size = get_opt_as!(int, cfg, rng, name, "room_size", usize)?;
+    let decay = get_opt_as!(float, cfg, rng, name, "decay", f64)?;
+    let mix = get_opt_as!(float, cfg, rng, name, "mix", f64)?;
+    let format = SampleFormat::from_options(cfg, name)?;
+
+    format.process(data, iterations, chunksize, rng, |samples| {
+        // Four parallel combs of different lengths, followed by two allpasses (Schroeder's reverb).
+        let mut wet = vec![0.0; samples.len()];
+        for scale in &[1.0, 1.137, 1.263, 1.381] {
+            let delayed = comb(samples, (room_size as f64 * scale) as usize, decay);
+            wet.iter_mut().zip(delayed).for_each(|(wet, delayed)| *wet += delayed / 4.0);
+        }
+
+        allpass(&mut wet, (room_size as f64 * 0.347) as usize, 0.7);
+        allpass(&mut wet, (room_size as f64 * 0.113) as usize, 0.7);
+
+        for (sample, wet) in samples.iter_mut().zip(wet) {
+            *sample = *sample * (1.0 - mix) + wet * mix;
+        }
+    });
+
+    Ok(format!("RVB_it={}_ch={}_rm={}_dc={}_mx={}_{}",
+        iterations, chunksize, room_size, decay, mix, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Configuration;
+    use rand_core::SeedableRng;
+
+    fn pixels(options: &[(&str, toml::Value)]) -> Configuration {
+        let mut builder = Configuration::builder().option("width", 4).option("channels", 1);
+        for (key, value) in options {
+            builder = builder.option(key, value.clone());
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn pixelsort_sorts_rows() {
+        let config = pixels(&[("lower", 0.0.into()), ("upper", 1.0.into())]);
+        let mut data = [200, 100, 150, 120, 4, 3, 2, 1];
+
+        pixelsort(&mut data, &config, &mut MutRng::seed_from_u64(0)).unwrap();
+        assert_eq!(data, [100, 120, 150, 200, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn pixelsort_only_sorts_within_range() {
+        let config = pixels(&[("lower", 0.0.into()), ("upper", 1.0.into()), ("range", "..4".into())]);
+        let mut data = [4, 3, 2, 1, 4, 3, 2, 1];
+
+        pixelsort(&mut data, &config, &mut MutRng::seed_from_u64(0)).unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn truncate_cuts_within_range() {
+        let cut = |config: Configuration| {
+            let mut data = vec![0; 1000];
+            truncate(&mut data, &config, &mut MutRng::seed_from_u64(0)).unwrap();
+            data.len()
+        };
+
+        assert_eq!(cut(Configuration::builder().option("chunksize", 10).build()), 990);
+        assert_eq!(cut(Configuration::builder().option("chunksize", 10).option("TruncateConfig.range", "..50%").build()), 490);
+        assert_eq!(cut(Configuration::builder().option("chunksize", 10).option("exclude", "..100%").build()), 1000);
+        assert_eq!(cut(Configuration::builder().option("chunksize", 10).option("exclude", "..100").build()), 990);
+    }
+
+    #[test]
+    fn truncate_keeps_excluded_tail() {
+        let config = Configuration::builder().option("chunksize", 10).option("exclude", "-100..").build();
+        let mut data: Vec<u8> = (0..1000).map(|index| index as u8).collect();
+        let original = data.clone();
+
+        let result = truncate(&mut data, &config, &mut MutRng::seed_from_u64(0));
+
+        assert!(matches!(result, Err(Error::InvalidOption { ref option, .. }) if option == "exclude"));
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn pixelsort_rejects_unknown_channel_orders() {
+        let config = pixels(&[("channel_order", "cmyk".into())]);
+        let result = pixelsort(&mut [0; 8], &config, &mut MutRng::seed_from_u64(0));
+
+        assert!(matches!(result, Err(Error::InvalidOption { ref option, .. }) if option == "channel_order"));
+    }
+
+    #[test]
+    fn pixelsort_rejects_invalid_thresholds() {
+        let config = pixels(&[("upper", "high".into())]);
+        let result = pixelsort(&mut [0; 8], &config, &mut MutRng::seed_from_u64(0));
+
+        assert!(matches!(result, Err(Error::InvalidOption { ref option, .. }) if option == "upper"));
+    }
+
+    fn sample_format(format: &str, endianness: &str) -> SampleFormat {
+        let config = Configuration::builder()
+            .option("sample_format", format)
+            .option("endianness", endianness)
+            .build();
+        SampleFormat::from_options(&config, "EchoConfig").unwrap()
+    }
+
+    #[test]
+    fn samples_round_trip_unchanged() {
+        let floats: Vec<u8> = [0.0f32, 0.5, -0.25, 1.0, -1.0, 0.123].iter().flat_map(|float| float.to_le_bytes().to_vec()).collect();
+        let cases: [(&str, Vec<u8>); 5] = [
+            ("u8", (0..=255).collect()),
+            ("s8", (0..=255).collect()),
+            ("u16", (0..=255).collect()),
+            ("s16", (0..=255).rev().collect()),
+            ("s24", (0..=254).collect()),
+        ];
+
+        for (format, data) in cases.iter() {
+            for endianness in &["little", "big"] {
+                let format = sample_format(format, endianness);
+                let mut written = vec![0; data.len()];
+
+                format.write(&format.read(data), &mut written);
+                assert_eq!(&written, data, "{}", format);
+            }
+        }
+
+        let format = sample_format("f32", "little");
+        let mut written = vec![0; floats.len()];
+        format.write(&format.read(&floats), &mut written);
+        assert_eq!(written, floats);
+    }
+
+    #[test]
+    fn samples_are_read_in_their_byte_order() {
+        assert_eq!(sample_format("s16", "little").read(&[0x00, 0x80, 0xff, 0x7f]), vec![-1.0, 32767.0 / 32768.0]);
+        assert_eq!(sample_format("s16", "big").read(&[0x80, 0x00, 0x7f, 0xff]), vec![-1.0, 32767.0 / 32768.0]);
+        assert_eq!(sample_format("s24", "little").read(&[0x00, 0x00, 0xc0]), vec![-0.5]);
+        assert_eq!(sample_format("s24", "big").read(&[0xc0, 0x00, 0x00]), vec![-0.5]);
+        assert_eq!(sample_format("u8", "big").read(&[0, 128, 192]), vec![-1.0, 0.0, 0.5]);
+        assert_eq!(sample_format("f32", "big").read(&0.75f32.to_be_bytes()), vec![0.75]);
+    }
+
+    #[test]
+    fn samples_clip_instead_of_wrapping() {
+        let cases: [(&str, &str, [u8; 8]); 5] = [
+            ("u8", "little", [255, 0, 255, 0, 255, 0, 255, 0]),
+            ("s8", "little", [127, 128, 127, 128, 127, 128, 127, 128]),
+            ("s16", "little", [0xff, 0x7f, 0x00, 0x80, 0xff, 0x7f, 0x00, 0x80]),
+            ("u16", "big", [0xff, 0xff, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00]),
+            ("f32", "little", [0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x80, 0xbf]),
+        ];
+
+        for (format, endianness, expected) in cases.iter() {
+            let format = sample_format(format, endianness);
+            let mut data = [0; 8];
+
+            format.write(&[2.0, -2.0, 1.5, -1.5, 2.0, -2.0, 1.5, -1.5], &mut data);
+            assert_eq!(&data, expected, "{}", format);
+        }
+
+        let mut data = [0; 6];
+        sample_format("s24", "little").write(&[3.0, -3.0], &mut data);
+        assert_eq!(data, [0xff, 0xff, 0x7f, 0x00, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn loud_echoes_clip() {
+        let config = Configuration::builder()
+            .option("iterations", 1)
+            .option("chunksize", 64)
+            .option("delay", 1)
+            .option("decay", 1.0)
+            .option("sample_format", "s16")
+            .option("range", "..100%")
+            .build();
+        let mut data: Vec<u8> = (0..32).flat_map(|_| 30_000i16.to_le_bytes().to_vec()).collect();
+
+        echo(&mut data, &config, &mut MutRng::seed_from_u64(0)).unwrap();
+
+        let samples: Vec<i16> = data.chunks(2).map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])).collect();
+        assert!(samples.iter().all(|&sample| sample == 30_000 || sample == i16::MAX));
+        assert!(samples.contains(&i16::MAX));
+    }
+
+    #[test]
+    fn lowpass_keeps_silence_silent() {
+        let config = Configuration::builder()
+            .option("iterations", 4)
+            .option("chunksize", 32)
+            .option("cutoff", 0.1)
+            .build();
+        let mut data = vec![128; 256];
+
+        lowpass(&mut data, &config, &mut MutRng::seed_from_u64(0)).unwrap();
+        assert_eq!(data, vec![128; 256]);
+    }
+
+    /// Runs a mutation over a single chunk of 8 bytes, at a position picked with a fixed seed.
+    fn bits(mutation: fn(&mut [u8], &Configuration, &mut MutRng) -> Result<String, Error>, option: &str, value: toml::Value) -> Result<[u8; 8], Error> {
+        let config = Configuration::builder()
+            .option("iterations", 1)
+            .option("chunksize", 8)
+            .option("range", "..100%")
+            .option(option, value)
+            .build();
+        let mut data = [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0];
+
+        mutation(&mut data, &config, &mut MutRng::seed_from_u64(0))?;
+        Ok(data)
+    }
+
+    #[test]
+    fn bitshift_crosses_byte_boundaries() {
+        // Both shift the last three bytes, 0xbcdef0.
+        assert_eq!(bits(bitshift, "shift_by", 4.into()).unwrap(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0xcd, 0xef, 0x00]);
+        assert_eq!(bits(bitshift, "shift_by", (-12).into()).unwrap(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0x00, 0x0b, 0xcd]);
+    }
+
+    #[test]
+    fn bitrotate_rotates_within_bytes() {
+        assert_eq!(bits(bitrotate, "rotate_by", 3.into()).unwrap(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0xe5, 0xf6, 0x87]);
+        assert_eq!(bits(bitrotate, "rotate_by", (-5).into()).unwrap(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0xe5, 0xf6, 0x87]);
+    }
+
+    #[test]
+    fn bitflip_flips_every_bit_at_full_probability() {
+        assert_eq!(bits(bitflip, "probability", 1.0.into()).unwrap(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0x43, 0x21, 0x0f]);
+        assert_eq!(bits(bitflip, "probability", 0.0.into()).unwrap(), [0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0]);
+    }
+
+    #[test]
+    fn xor_repeats_the_key() {
+        assert_eq!(bits(xor, "key", "ff00".into()).unwrap(), [0xed, 0x34, 0xa9, 0x78, 0x65, 0xbc, 0x21, 0xf0]);
+        assert_eq!(bits(xor, "key", "0xFF00".into()).unwrap(), [0xed, 0x34, 0xa9, 0x78, 0x65, 0xbc, 0x21, 0xf0]);
+    }
+
+    #[test]
+    fn xor_rejects_invalid_keys() {
+        for key in &["", "0x", "abc", "zz"] {
+            let result = bits(xor, "key", (*key).into());
+            assert!(matches!(result, Err(Error::InvalidOption { ref option, .. }) if option == "key"), "{}", key);
+        }
+    }
+}
+
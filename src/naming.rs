@@ -0,0 +1,222 @@
+//! Names of outputs, from the `output_template` and `on_collision` options.
+
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Every placeholder a template can use.
+pub const PLACEHOLDERS: &[&str] = &["stem", "ext", "run", "index", "seed", "muts", "mutshort", "hash", "date"];
+
+/// File names longer than this can't be created on most filesystems.
+const MAX_NAME: usize = 255;
+
+/// What an output can be named after.
+pub struct Fields<'a> {
+    /// The name of the output file, without its directory or extension.
+    pub stem: &'a str,
+    /// The extension of the output file.
+    pub ext: &'a str,
+    /// Which of the `times` the output comes from.
+    pub run: usize,
+    /// Which list of mutations the output comes from.
+    pub index: usize,
+    /// The seed of the list of mutations.
+    pub seed: u64,
+    /// The log of every mutation applied.
+    pub log: &'a [String],
+}
+
+impl Fields<'_> {
+    fn value(&self, placeholder: &str) -> Option<String> {
+        let value = match placeholder {
+            "stem" => self.stem.to_string(),
+            "ext" => self.ext.to_string(),
+            "run" => self.run.to_string(),
+            "index" => self.index.to_string(),
+            "seed" => self.seed.to_string(),
+            "muts" => self.log.join("---"),
+            // Only the code of each mutation, such as `SFT-CHS`.
+            "mutshort" => self.log.iter()
+                .map(|log| log.split('_').next().unwrap_or_default())
+                .collect::<Vec<_>>()
+                .join("-"),
+            "hash" => self.hash(),
+            "date" => date(),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// A short hash of the seed and every mutation applied, which tells outputs apart.
+    fn hash(&self) -> String {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(self.seed.to_string().as_bytes());
+        hasher.update(self.log.join("---").as_bytes());
+        format!("{:08x}", hasher.finalize())
+    }
+}
+
+/// Fills in the placeholders of a template, such as `{stem}_{index:04}.{ext}`.
+///
+/// A placeholder can be padded to a width after a colon, with zeros if the width starts with one.
+/// `{{` and `}}` stand for literal braces. Returns what's wrong with the template if it's invalid.
+pub fn render(template: &str, fields: &Fields) -> Result<String, String> {
+    let mut name = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(&['{', '}'][..]) {
+        name.push_str(&rest[..start]);
+
+        if rest[start..].starts_with("{{") || rest[start..].starts_with("}}") {
+            name.push_str(&rest[start..=start]);
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        let end = match rest[start..].find('}') {
+            Some(end) if rest.as_bytes()[start] == b'{' => start + end,
+            _ => return Err(format!("'{}' has an unmatched brace. Use '{{{{' or '}}}}' for literal braces.", template)),
+        };
+
+        let mut placeholder = rest[start + 1..end].splitn(2, ':');
+        let key = placeholder.next().unwrap_or_default();
+        let value = fields.value(key)
+            .ok_or_else(|| format!("'{{{}}}' isn't a placeholder. Only allowed placeholders: {:?}", key, PLACEHOLDERS))?;
+
+        match placeholder.next() {
+            Some(width) => {
+                let padding = width.parse::<usize>()
+                    .map_err(|_| format!("'{}' in '{{{}:{}}}' isn't a width.", width, key, width))?;
+
+                if width.starts_with('0') {
+                    name.push_str(&format!("{:0>width$}", value, width = padding));
+                } else {
+                    name.push_str(&format!("{:>width$}", value, width = padding));
+                }
+            },
+            None => name.push_str(&value),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    name.push_str(rest);
+    Ok(name)
+}
+
+/// Checks a template, returning what's wrong with it if it's invalid.
+pub fn check(template: &str) -> Result<(), String> {
+    let fields = Fields { stem: "", ext: "", run: 0, index: 0, seed: 0, log: &[] };
+    render(template, &fields).map(|_| ())
+}
+
+/// Shortens a name that's too long for the filesystem, keeping it unique by ending it with the hash.
+pub fn fit(name: String, fields: &Fields) -> String {
+    let path = Path::new(&name);
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+
+    if file_name.len() <= MAX_NAME {
+        return name;
+    }
+
+    let extension = path.extension().and_then(|ext| ext.to_str()).map_or(String::new(), |ext| format!(".{}", ext));
+    let stem = &file_name[..file_name.len() - extension.len()];
+    let hash = format!("~{}", fields.hash());
+
+    // Cuts the stem on a character boundary, leaving room for the hash and extension.
+    let mut cut = MAX_NAME.saturating_sub(hash.len() + extension.len()).min(stem.len());
+    while !stem.is_char_boundary(cut) {
+        cut -= 1;
+    }
+
+    let short = format!("{}{}{}", &stem[..cut], hash, extension);
+    eprintln!("Shortening '{}' to '{}' due to length...", file_name, short);
+
+    match path.parent().and_then(|parent| parent.to_str()).filter(|parent| !parent.is_empty()) {
+        Some(parent) => format!("{}/{}", parent, short),
+        None => short,
+    }
+}
+
+/// What happens when an output already exists.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collision {
+    /// The output isn't written.
+    Skip,
+    /// The existing file is replaced.
+    Overwrite,
+    /// The output is written next to it instead, as `name~2.ext`, `name~3.ext`, and so on.
+    Suffix,
+}
+
+impl Collision {
+    /// Parses the `on_collision` option.
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "skip" => Some(Collision::Skip),
+            "overwrite" => Some(Collision::Overwrite),
+            "suffix" => Some(Collision::Suffix),
+            _ => None,
+        }
+    }
+
+    /// Claims the name an output is written to, or `None` if it should be skipped.
+    ///
+    /// The name is created right away, so that outputs written at the same time can't take the same one.
+    pub fn claim(self, name: &str) -> std::io::Result<Option<String>> {
+        if self == Collision::Overwrite {
+            return Ok(Some(name.to_string()));
+        }
+
+        for attempt in 1.. {
+            let candidate = match attempt {
+                1 => name.to_string(),
+                _ => suffixed(name, attempt),
+            };
+
+            match OpenOptions::new().write(true).create_new(true).open(&candidate) {
+                Ok(_) => return Ok(Some(candidate)),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => if self == Collision::Skip {
+                    return Ok(None);
+                },
+                Err(err) => return Err(err),
+            }
+        }
+
+        unreachable!("A free name is always found eventually.")
+    }
+}
+
+/// Adds a suffix before the extension of a name, such as `name~2.ext`.
+fn suffixed(name: &str, attempt: usize) -> String {
+    let path = Path::new(name);
+
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => {
+            let file_name = format!("{}~{}.{}", stem.to_string_lossy(), attempt, ext.to_string_lossy());
+            path.with_file_name(file_name).to_string_lossy().into_owned()
+        },
+        _ => format!("{}~{}", name, attempt),
+    }
+}
+
+/// The current date and time in UTC, as `YYYYMMDD-HHMMSS`.
+fn date() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // Converts days since 1970 to a civil date, from Howard Hinnant's algorithm.
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
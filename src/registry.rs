@@ -0,0 +1,265 @@
+use super::mutations::*;
+
+use super::configuration::Configuration;
+use super::error::Error;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+/// The signature of every built-in mutation.
+pub type Mut = fn(&mut [u8], &Configuration, &mut MutRng) -> Result<String, Error>;
+
+/// A mutation defined by a plain function.
+pub struct Builtin {
+    name: &'static str,
+    code: &'static str,
+    options: &'static [Opt],
+    func: Mut,
+}
+
+impl Mutation for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn code(&self) -> &str {
+        self.code
+    }
+
+    fn options(&self) -> Vec<OptionSpec> {
+        self.options.iter().map(|&option| spec(option)).collect()
+    }
+
+    fn apply(&self, data: &mut [u8], cfg: &Configuration, rng: &mut MutRng) -> Result<String, Error> {
+        (self.func)(data, cfg, rng)
+    }
+}
+
+/// The signature of every built-in mutation that changes the size of the data.
+pub type ResizingMut = fn(&mut Vec<u8>, &Configuration, &mut MutRng) -> Result<String, Error>;
+
+/// A mutation that changes the size of the data, defined by a plain function.
+pub struct Resizing {
+    name: &'static str,
+    code: &'static str,
+    options: &'static [Opt],
+    func: ResizingMut,
+}
+
+impl Mutation for Resizing {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn code(&self) -> &str {
+        self.code
+    }
+
+    fn options(&self) -> Vec<OptionSpec> {
+        self.options.iter().map(|&option| spec(option)).collect()
+    }
+
+    fn apply(&self, _data: &mut [u8], _cfg: &Configuration, _rng: &mut MutRng) -> Result<String, Error> {
+        Err(Error::Config(format!("{} changes the size of the data, which can't be done in place.", self.name)))
+    }
+
+    fn resizes(&self) -> bool {
+        true
+    }
+
+    fn apply_resizable(&self, data: &mut Vec<u8>, cfg: &Configuration, rng: &mut MutRng) -> Result<String, Error> {
+        (self.func)(data, cfg, rng)
+    }
+}
+
+/// What values a built-in option accepts, checked before any work starts.
+#[derive(Clone, Copy)]
+enum Bound {
+    /// Any value of the option's kind.
+    Any,
+    /// Values no lower than this.
+    AtLeast(f64),
+    /// Any value, or none at all, for mutations that fall back to a value of their own.
+    Optional,
+}
+
+/// A built-in option: its name, the short name used in logs, its kind, and what values it accepts.
+type Opt = (&'static str, &'static str, OptionKind, Bound);
+
+/// Describes a built-in option.
+fn spec((name, short, kind, bound): Opt) -> OptionSpec {
+    let spec = OptionSpec::new(name, short, kind);
+
+    match bound {
+        Bound::Any => spec,
+        Bound::AtLeast(min) => spec.at_least(min),
+        Bound::Optional => spec.optional(),
+    }
+}
+
+const IT: Opt = ("iterations", "it", OptionKind::Int, Bound::AtLeast(0.0));
+const CH: Opt = ("chunksize", "ch", OptionKind::Int, Bound::AtLeast(1.0));
+
+// Options of the audio effects.
+const FMT: Opt = ("sample_format", "fmt", OptionKind::Text, Bound::Optional);
+const END: Opt = ("endianness", "end", OptionKind::Text, Bound::Optional);
+
+/// Every mutation that can be used, indexed by name.
+pub struct Registry {
+    mutations: HashMap<String, Arc<dyn Mutation>>,
+}
+
+impl Registry {
+    /// Creates a registry containing only the built-in mutations.
+    pub fn new() -> Self {
+        let mut registry = Registry { mutations: HashMap::new() };
+
+        use self::Bound::{Any, AtLeast, Optional};
+        use OptionKind::{Float, Int, Text};
+
+        let builtins = vec![
+            Builtin { name: "Void",     code: "VOID", options: &[IT, CH], func: void },
+            Builtin { name: "Chaos",    code: "CHS",  options: &[IT, CH], func: chaos },
+            Builtin { name: "Loops",    code: "LPS",  options: &[IT, CH, ("loops", "lps", Int, AtLeast(0.0))], func: loops },
+            Builtin { name: "Reverse",  code: "RVR",  options: &[IT, CH], func: reverse },
+            Builtin { name: "Shift",    code: "SFT",  options: &[IT, CH], func: shift },
+            Builtin { name: "Shuffle",  code: "SHF",  options: &[IT, CH], func: shuffle },
+            Builtin { name: "Swap",     code: "SWP",  options: &[IT, CH], func: swap },
+            Builtin { name: "Increase", code: "INC",  options: &[IT, CH, ("increase_by", "by", Int, AtLeast(0.0))], func: increase },
+            Builtin { name: "Gradient", code: "GRT",  options: &[IT, CH, ("accelerate_by", "by", Int, AtLeast(0.0)),
+                                                                 ("accelerate_in", "in", Int, AtLeast(0.0))], func: gradient },
+            Builtin { name: "Multiply", code: "MUL",  options: &[IT, CH, ("multiply_by", "by", Float, Any)], func: multiply },
+            Builtin { name: "Compress", code: "CMP",  options: &[IT, CH, ("compress_by", "by", Int, AtLeast(1.0))], func: compress },
+            Builtin { name: "BitFlip",   code: "BFL", options: &[IT, CH, ("probability", "p", Float, AtLeast(0.0))], func: bitflip },
+            Builtin { name: "BitRotate", code: "BRT", options: &[IT, CH, ("rotate_by", "by", Int, Any)], func: bitrotate },
+            Builtin { name: "BitShift",  code: "BSH", options: &[IT, CH, ("shift_by", "by", Int, Any)], func: bitshift },
+            Builtin { name: "Xor",       code: "XOR", options: &[IT, CH, ("key", "key", Text, Optional)], func: xor },
+            Builtin { name: "Splice",    code: "SPL", options: &[IT, CH], func: splice },
+            Builtin { name: "PixelSort", code: "PXS", options: &[("sort_by", "by", Text, Optional), ("lower", "lo", Float, Optional),
+                                                                ("upper", "hi", Float, Optional),
+                                                                ("angle", "ang", Float, Optional)], func: pixelsort },
+            Builtin { name: "Echo",       code: "ECH", options: &[IT, CH, ("delay", "dl", Int, AtLeast(0.0)),
+                                                                ("decay", "dc", Float, AtLeast(0.0)), FMT, END], func: echo },
+            Builtin { name: "Reverb",     code: "RVB", options: &[IT, CH, ("room_size", "rm", Int, AtLeast(0.0)),
+                                                                ("decay", "dc", Float, AtLeast(0.0)),
+                                                                ("mix", "mx", Float, AtLeast(0.0)), FMT, END], func: reverb },
+            Builtin { name: "LowPass",    code: "LPF", options: &[IT, CH, ("cutoff", "cut", Float, AtLeast(0.0)), FMT, END], func: lowpass },
+            Builtin { name: "HighPass",   code: "HPF", options: &[IT, CH, ("cutoff", "cut", Float, AtLeast(0.0)), FMT, END], func: highpass },
+            Builtin { name: "Phaser",     code: "PHS", options: &[IT, CH, ("period", "per", Int, AtLeast(0.0)),
+                                                                ("depth", "dp", Float, AtLeast(0.0)),
+                                                                ("feedback", "fb", Float, Any), FMT, END], func: phaser },
+            Builtin { name: "Distortion", code: "DST", options: &[IT, CH, ("drive", "dr", Float, AtLeast(0.0)), FMT, END], func: distortion },
+            // Add more mutations here.
+        ];
+
+        let resizing = vec![
+            Resizing { name: "Insert",    code: "INS", options: &[IT, CH, ("fill", "fill", Text, Optional)], func: insert },
+            Resizing { name: "Delete",    code: "DEL", options: &[IT, CH], func: delete },
+            Resizing { name: "Duplicate", code: "DUP", options: &[IT, CH], func: duplicate },
+            Resizing { name: "Truncate",  code: "TRC", options: &[CH], func: truncate },
+            Resizing { name: "DropKeyframes", code: "DKF", options: &[], func: drop_keyframes },
+            Resizing { name: "Bloom",         code: "BLM", options: &[IT, ("repeat", "rep", Int, AtLeast(0.0))], func: bloom },
+            Resizing { name: "FrameSplice",   code: "FSP", options: &[IT, ("frames", "fr", Int, AtLeast(0.0))], func: frame_splice },
+        ];
+
+        for builtin in builtins {
+            registry.register(builtin);
+        }
+
+        for builtin in resizing {
+            registry.register(builtin);
+        }
+
+        registry
+    }
+
+    /// Adds a mutation, replacing any mutation with the same name.
+    pub fn register<M: Mutation + 'static>(&mut self, mutation: M) -> Option<Arc<dyn Mutation>> {
+        self.mutations.insert(mutation.name().to_string(), Arc::new(mutation))
+    }
+
+    /// Retrieves a mutation by its name.
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Mutation>> {
+        self.mutations.get(name).cloned()
+    }
+
+    /// Retrieves a mutation by the code starting its log.
+    pub fn get_by_code(&self, code: &str) -> Option<Arc<dyn Mutation>> {
+        self.mutations.values().find(|mutation| mutation.code() == code).cloned()
+    }
+
+    /// The names of every mutation, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.mutations.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+// Every mutation available to the bender.
+lazy_static! {
+    pub static ref REGISTRY: RwLock<Registry> = RwLock::new(Registry::new());
+}
+
+/// Adds a mutation to the global registry, making it usable in `mutations`.
+pub fn register<M: Mutation + 'static>(mutation: M) -> Option<Arc<dyn Mutation>> {
+    REGISTRY.write().unwrap().register(mutation)
+}
+
+/// Retrieves a mutation from the global registry by its name.
+pub fn get(name: &str) -> Option<Arc<dyn Mutation>> {
+    REGISTRY.read().unwrap().get(name)
+}
+
+/// Retrieves a mutation from the global registry by the code starting its log.
+pub fn get_by_code(code: &str) -> Option<Arc<dyn Mutation>> {
+    REGISTRY.read().unwrap().get_by_code(code)
+}
+
+/// The names of every mutation in the global registry, sorted.
+pub fn names() -> Vec<String> {
+    REGISTRY.read().unwrap().names()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    #[test]
+    fn mutations_resolve_by_name_and_code() {
+        let registry = Registry::new();
+        assert!(!registry.names().is_empty());
+
+        for name in registry.names() {
+            let mutation = registry.get(&name).unwrap();
+            assert_eq!(mutation.name(), name);
+            assert_eq!(registry.get_by_code(mutation.code()).map(|found| found.name().to_string()), Some(name.clone()));
+            assert_eq!(get(&name).map(|found| found.code().to_string()), Some(mutation.code().to_string()));
+        }
+
+        assert!(registry.get("Unknown").is_none());
+        assert!(registry.get_by_code("???").is_none());
+    }
+
+    #[test]
+    fn codes_are_unique() {
+        let registry = Registry::new();
+        let mut codes = HashSet::new();
+
+        for name in registry.names() {
+            let code = registry.get(&name).unwrap().code().to_string();
+            assert!(!code.contains(&['_', '='][..]), "{} can't be told apart in logs", code);
+            assert!(codes.insert(code.clone()), "{} is used twice", code);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use super::configuration::Configuration;
+use super::mutations::OptionKind;
+use super::registry;
+
+use serde::{Serialize, Deserialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// A single resolved option of a mutation.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Param {
+    Int(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl Param {
+    fn parse(value: &str, kind: OptionKind) -> Option<Self> {
+        match kind {
+            OptionKind::Int => value.parse().map(Param::Int).ok(),
+            OptionKind::Float => value.parse().map(Param::Float).ok(),
+            OptionKind::Text => Some(Param::Text(value.into())),
+        }
+    }
+
+    fn to_toml(&self) -> toml::Value {
+        match self {
+            Param::Int(int) => toml::Value::Integer(*int),
+            Param::Float(float) => toml::Value::Float(*float),
+            Param::Text(text) => toml::Value::String(text.clone()),
+        }
+    }
+}
+
+/// How long a mutation took, and which bytes it changed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Timing {
+    pub millis: f64,
+    /// Each range of bytes changed, as `[start, end]` offsets into the data mutations work on.
+    /// For formats, this is the decoded data rather than the file.
+    pub touched: Vec<[usize; 2]>,
+}
+
+/// A mutation that was applied, along with the exact options it used.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Step {
+    pub name: String,
+    pub options: BTreeMap<String, Param>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+}
+
+impl Step {
+    /// Parses a mutation's log, such as `SFT_it=42_ch=1200`.
+    pub fn parse(log: &str) -> Result<Self, String> {
+        let mut parts = log.split('_');
+        let code = parts.next().unwrap_or_default();
+
+        let mutation = registry::get_by_code(code)
+            .ok_or_else(|| format!("Unknown mutation code '{}'.", code))?;
+        let specs = mutation.options();
+
+        let mut options = BTreeMap::new();
+
+        for part in parts {
+            let mut pair = part.splitn(2, '=');
+            let (key, value) = (pair.next().unwrap_or_default(), pair.next().unwrap_or_default());
+
+            let spec = specs.iter()
+                .find(|spec| spec.short == key)
+                .ok_or_else(|| format!("Unknown option '{}' in '{}'.", key, log))?;
+            let value = Param::parse(value, spec.kind)
+                .ok_or_else(|| format!("Invalid value '{}' in '{}'.", value, log))?;
+
+            options.insert(spec.name.to_string(), value);
+        }
+
+        if options.len() != specs.len() {
+            return Err(format!("Missing options in '{}'. The name might have been truncated.", log));
+        }
+
+        Ok(Step { name: mutation.name().to_string(), options, timing: None })
+    }
+
+    /// Creates a configuration that fixes every option of the mutation to what was used.
+    /// 
+    /// Options that aren't logged, such as `align` or files, are still read from `base`.
+    pub fn to_configuration(&self, base: &Configuration) -> Configuration {
+        let mut configuration = base.clone();
+
+        for (key, value) in &self.options {
+            configuration.set(&format!("{}Config.{}", self.name, key), value.to_toml());
+        }
+
+        configuration
+    }
+}
+
+/// Everything needed to generate an output again from its input.
+/// 
+/// Manifests also record what's needed to catalogue outputs, which isn't used to replay them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Recipe {
+    #[serde(default)]
+    pub input: Option<String>,
+    /// The CRC-32 of the input, in hexadecimal.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_crc32: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    pub seed: u64,
+    /// The frame of a sequence the output was, which `drift` depends on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame: Option<u64>,
+    /// How long every mutation took in total.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub millis: Option<f64>,
+    /// The exact configuration used, after every override.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config: Option<toml::Value>,
+    pub mutations: Vec<Step>,
+}
+
+/// A file that couldn't be bent, in the manifest of a run.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Failure {
+    pub input: String,
+    pub error: String,
+}
+
+/// Every output of a run, written to `run_manifest`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RunManifest {
+    pub seed: u64,
+    pub millis: f64,
+    pub outputs: Vec<Recipe>,
+    #[serde(default)]
+    pub failures: Vec<Failure>,
+}
+
+impl RunManifest {
+    /// Writes the manifest as JSON.
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)?;
+        fs::write(path, json)
+    }
+}
+
+impl Recipe {
+    /// Creates a recipe from the log of mutations applied to a file.
+    pub fn from_log(input: &str, seed: u64, log: &[String]) -> Result<Self, String> {
+        Ok(Recipe {
+            input: Some(input.into()),
+            input_crc32: None,
+            output: None,
+            seed,
+            frame: None,
+            millis: None,
+            config: None,
+            mutations: log.iter().map(|log| Step::parse(log)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Loads the recipe of a previous output.
+    /// 
+    /// `path` can be either a manifest, or an output generated by glitchup.
+    /// In the latter case, the manifest next to it is used if it exists.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let manifest = if path.ends_with(".json") {
+            path.to_string()
+        } else {
+            format!("{}.json", path)
+        };
+
+        if Path::new(&manifest).exists() {
+            Recipe::from_manifest(&manifest)
+        } else {
+            Recipe::from_filename(path)
+        }
+    }
+
+    /// Reads a recipe from a JSON manifest.
+    pub fn from_manifest(path: &str) -> Result<Self, String> {
+        let file = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        serde_json::from_str(&file).map_err(|err| err.to_string())
+    }
+
+    /// Parses a recipe from the name of an output, such as `name__SEED=5__SFT_it=42_ch=1200.png`.
+    pub fn from_filename(path: &str) -> Result<Self, String> {
+        let stem = Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("'{}' is not a valid file name.", path))?;
+
+        // Outputs written next to one with the same name end with a suffix, such as `~2`.
+        let stem = match stem.rfind('~') {
+            Some(suffix) if stem[suffix + 1..].parse::<usize>().is_ok() => &stem[..suffix],
+            _ => stem,
+        };
+
+        let start = stem.rfind("__SEED=")
+            .ok_or_else(|| format!("'{}' doesn't contain a seed.", path))?;
+        let mut parts = stem[start + "__SEED=".len()..].splitn(2, "__");
+
+        let seed = parts.next().unwrap_or_default().parse()
+            .map_err(|_| format!("'{}' doesn't contain a valid seed.", path))?;
+        let mutations = parts.next()
+            .ok_or_else(|| format!("'{}' doesn't contain any mutations.", path))?
+            .split("---")
+            .map(Step::parse)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Recipe { input: None, input_crc32: None, output: None, seed, frame: None, millis: None, config: None, mutations })
+    }
+
+    /// Writes the recipe as a JSON manifest.
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::other)?;
+        fs::write(path, json)
+    }
+
+    /// Applies the recipe on top of a configuration, with its mutations as the only list.
+    /// 
+    /// The input and frame of the recipe are only used if `inputfile` and `frame` aren't already specified.
+    pub fn apply_to(&self, configuration: &Configuration) -> Configuration {
+        let mut configuration = configuration.clone();
+
+        let names = self.mutations.iter().map(|step| toml::Value::String(step.name.clone())).collect();
+        configuration.set("mutations", toml::Value::Array(vec![toml::Value::Array(names)]));
+
+        if let (None, Some(input)) = (configuration.get("inputfile"), &self.input) {
+            configuration.set("inputfile", toml::Value::String(input.clone()));
+        }
+
+        if let (None, Some(frame)) = (configuration.get("frame"), self.frame) {
+            configuration.set("frame", toml::Value::Integer(frame as i64));
+        }
+
+        configuration.set("seed", toml::Value::Integer(self.seed as i64));
+        configuration
+    }
+}
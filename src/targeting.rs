@@ -0,0 +1,385 @@
+//! Which parts of the data mutations may touch, from the `range` and `exclude` options.
+
+use std::ops::Range;
+
+use rand::Rng;
+
+use crate::configuration::Configuration;
+use crate::error::Error;
+use crate::mutations::{MutRng, align_down, align_up, index_boundary};
+
+/// A position in the data, resolved once its length is known.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bound {
+    /// An offset from the start.
+    Start(usize),
+    /// An offset from the end, written as a negative number.
+    End(usize),
+    /// A percentage of the length.
+    Percent(f64),
+}
+
+impl Bound {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().replace('_', "");
+
+        if let Some(percent) = text.strip_suffix('%') {
+            return percent.trim().parse().ok()
+                .filter(|percent| (0.0..=100.0).contains(percent))
+                .map(Bound::Percent);
+        }
+
+        match text.strip_prefix('-') {
+            Some(offset) => offset.parse().ok().map(Bound::End),
+            None => text.parse().ok().map(Bound::Start),
+        }
+    }
+
+    fn resolve(self, len: usize) -> usize {
+        match self {
+            Bound::Start(offset) => offset.min(len),
+            Bound::End(offset) => len.saturating_sub(offset),
+            Bound::Percent(percent) => ((len as f64 * percent / 100.0) as usize).min(len),
+        }
+    }
+}
+
+/// A span of the data, such as `10%..90%`, `1024..-128` or `..4096`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Span {
+    start: Bound,
+    end: Bound,
+}
+
+impl Span {
+    fn parse(text: &str) -> Option<Self> {
+        let mut bounds = text.splitn(2, "..");
+        let (start, end) = (bounds.next()?.trim(), bounds.next()?.trim());
+
+        Some(Span {
+            start: if start.is_empty() { Bound::Start(0) } else { Bound::parse(start)? },
+            end: if end.is_empty() { Bound::End(0) } else { Bound::parse(end)? },
+        })
+    }
+
+    fn resolve(&self, len: usize) -> Range<usize> {
+        self.start.resolve(len)..self.end.resolve(len)
+    }
+}
+
+/// The parts of the data a mutation may touch.
+///
+/// By default, this is everything but the first 2% of the data. The `range` option replaces this
+/// with spans of its own, while `exclude` removes spans from it.
+///
+/// In a sequence, `drift` also moves every position picked by this many bytes for each frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Target {
+    range: Option<Vec<Span>>,
+    exclude: Vec<Span>,
+    shift: i64,
+}
+
+impl Target {
+    /// Reads the `range` and `exclude` options, set either under the mutation's section or globally.
+    ///
+    /// Each is either a single span or a list of them, where a span is written as `start..end`.
+    /// Both ends can be an offset, a negative offset from the end, or a percentage, and either can be left out.
+    pub fn from_config(cfg: &Configuration, name: &str) -> Result<Self, Error> {
+        let spans = |option: &str| -> Result<Option<Vec<Span>>, Error> {
+            let error = || Error::invalid_option(name, option, "a span such as \"10%..90%\" or \"1024..-128\", or a list of them");
+
+            let value = match cfg.get_option(name, option) {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+
+            let texts = match value.as_str() {
+                Some(text) => vec![text],
+                None => value.as_list().ok_or_else(error)?
+                    .iter()
+                    .map(|text| text.as_str().ok_or_else(error))
+                    .collect::<Result<_, _>>()?,
+            };
+
+            texts.into_iter()
+                .map(|text| Span::parse(text).ok_or_else(error))
+                .collect::<Result<_, _>>()
+                .map(Some)
+        };
+
+        let drift = match cfg.get_option(name, "drift") {
+            Some(drift) => *drift.as_int().ok_or_else(|| Error::invalid_option(name, "drift", "an integer"))?,
+            None => 0,
+        };
+
+        // Only frames of a sequence have an index.
+        let frame = cfg.get("frame").and_then(|frame| frame.as_int()).cloned().unwrap_or(0);
+
+        Ok(Target {
+            range: spans("range")?,
+            exclude: spans("exclude")?.unwrap_or_default(),
+            shift: drift.wrapping_mul(frame),
+        })
+    }
+
+    /// Whether neither `range` nor `exclude` is set.
+    pub fn is_default(&self) -> bool {
+        self.range.is_none() && self.exclude.is_empty()
+    }
+
+    /// The spans `exclude` protects, once resolved for data of `len` bytes.
+    pub fn excluded(&self, len: usize) -> impl Iterator<Item = Range<usize>> + '_ {
+        self.exclude.iter().map(move |span| span.resolve(len)).filter(|span| span.start < span.end)
+    }
+
+    /// Resolves the target for data of `len` bytes, into regions starting and ending on multiples of `align`.
+    pub fn regions(&self, len: usize, align: usize) -> Regions {
+        let (min, max) = index_boundary(len, align);
+
+        if self.is_default() {
+            return Regions { regions: vec![Range { start: min, end: max }], explicit: false, shift: self.shift };
+        }
+
+        let mut included: Vec<Range<usize>> = match &self.range {
+            Some(spans) => spans.iter().map(|span| span.resolve(len)).collect(),
+            None => vec![Range { start: min, end: max }],
+        };
+        included.sort_by_key(|region| region.start);
+
+        // Merges the spans that overlap, so that no byte is more likely to be picked than another.
+        let mut regions: Vec<Range<usize>> = Vec::new();
+        for region in included.into_iter().filter(|region| region.start < region.end) {
+            match regions.last_mut() {
+                Some(last) if region.start <= last.end => last.end = last.end.max(region.end),
+                _ => regions.push(region),
+            }
+        }
+
+        for exclude in self.excluded(len) {
+            regions = regions.into_iter()
+                .flat_map(|region| vec![region.start..region.end.min(exclude.start), region.start.max(exclude.end)..region.end])
+                .filter(|region| region.start < region.end)
+                .collect();
+        }
+
+        let regions = regions.into_iter()
+            .map(|region| {
+                let start = align_up(region.start, align);
+                start..start + align_down(region.end.saturating_sub(start), align)
+            })
+            .filter(|region| region.start < region.end)
+            .collect();
+
+        Regions { regions, explicit: true, shift: self.shift }
+    }
+}
+
+/// The regions of the data a mutation may touch, once resolved for its length.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regions {
+    regions: Vec<Range<usize>>,
+    explicit: bool,
+    shift: i64,
+}
+
+impl Regions {
+    /// Picks an index from any region, every byte being as likely.
+    pub fn pick(&self, rng: &mut MutRng, align: usize) -> usize {
+        match self.regions.as_slice() {
+            [] => 0,
+            // Data smaller than a single unit leaves nothing to pick from.
+            [region] if region.end <= region.start => region.start,
+            [region] => align_down(self.drift(rng.gen_range(region.start, region.end), region.clone()), align),
+            regions => {
+                let mut draw = self.drift(rng.gen_range(0, self.total()), 0..self.total());
+                for region in regions {
+                    if draw < region.len() {
+                        return align_down(region.start + draw, align);
+                    }
+                    draw -= region.len();
+                }
+                regions[regions.len() - 1].start
+            },
+        }
+    }
+
+    /// Moves an index drawn from `within` by the `drift` of the frame, wrapping around to its start.
+    ///
+    /// Used by mutations that draw positions themselves. Outside of a sequence, the index is left as is.
+    pub fn drift(&self, index: usize, within: Range<usize>) -> usize {
+        if self.shift == 0 || within.is_empty() {
+            return index;
+        }
+
+        let offset = self.shift.rem_euclid(within.len() as i64) as usize;
+        within.start + (index - within.start + offset) % within.len()
+    }
+
+    /// The chunk of `chunksize` bytes starting at `start`, cut short at the end of its region.
+    pub fn chunk(&self, start: usize, chunksize: usize) -> Range<usize> {
+        let end = self.regions.iter()
+            .find(|region| region.contains(&start))
+            .map_or(start, |region| region.end);
+
+        start..end.min(start + chunksize)
+    }
+
+    /// Picks a single region with more than `room` bytes, in proportion to their length.
+    ///
+    /// Used by mutations that move data around within a region. Doesn't draw from `rng` if there's only one.
+    pub fn pick_region(&self, room: usize, rng: &mut MutRng) -> Option<Range<usize>> {
+        let eligible: Vec<&Range<usize>> = self.regions.iter().filter(|region| region.len() > room).collect();
+
+        match eligible.as_slice() {
+            [] => None,
+            [region] => Some((*region).clone()),
+            regions => {
+                let mut draw = rng.gen_range(0, regions.iter().map(|region| region.len()).sum::<usize>());
+                for region in regions {
+                    if draw < region.len() {
+                        return Some((*region).clone());
+                    }
+                    draw -= region.len();
+                }
+                None
+            },
+        }
+    }
+
+    /// Whether `index` is within any region.
+    pub fn contains(&self, index: usize) -> bool {
+        self.regions.iter().any(|region| region.contains(&index))
+    }
+
+    /// The region furthest into the data.
+    pub fn last(&self) -> Option<Range<usize>> {
+        self.regions.last().cloned()
+    }
+
+    /// Whether there's nothing to pick from, which only happens when `range` or `exclude` leave nothing.
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// The length of the largest region.
+    pub fn largest(&self) -> usize {
+        self.regions.iter().map(|region| region.len()).max().unwrap_or(0)
+    }
+
+    /// The total length of every region.
+    pub fn total(&self) -> usize {
+        self.regions.iter().map(|region| region.len()).sum()
+    }
+
+    /// Fails unless a region has more than `room` bytes.
+    pub fn require_room(&self, len: usize, room: usize, align: usize, mutation: &str) -> Result<(), Error> {
+        if self.largest() > room {
+            Ok(())
+        } else if self.explicit {
+            let section = format!("{}Config", mutation);
+            let expected = format!("spans leaving more than {} bytes in a row, rather than {}", room, self.largest());
+            Err(Error::invalid_option(&section, "range", expected))
+        } else {
+            // Indices are never picked from the first 2% of the data.
+            Err(Error::DataTooSmall { mutation: mutation.into(), len, needed: room + room / 49 + align })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_core::SeedableRng;
+
+    fn target(options: &[(&str, toml::Value)]) -> Target {
+        let mut builder = Configuration::builder();
+        for (key, value) in options {
+            builder = builder.option(key, value.clone());
+        }
+        Target::from_config(&builder.build(), "ShiftConfig").unwrap()
+    }
+
+    #[test]
+    fn spans_parse_every_kind_of_bound() {
+        assert_eq!(Span::parse("10%..90%"), Some(Span { start: Bound::Percent(10.0), end: Bound::Percent(90.0) }));
+        assert_eq!(Span::parse("..-128"), Some(Span { start: Bound::Start(0), end: Bound::End(128) }));
+        assert_eq!(Span::parse("1_024.."), Some(Span { start: Bound::Start(1024), end: Bound::End(0) }));
+
+        assert_eq!(Span::parse("150%.."), None);
+        assert_eq!(Span::parse("12"), None);
+        assert_eq!(Span::parse("a..b"), None);
+    }
+
+    #[test]
+    fn spans_resolve_against_the_length() {
+        assert_eq!(Span::parse("10%..90%").unwrap().resolve(1000), 100..900);
+        assert_eq!(Span::parse("..-128").unwrap().resolve(1000), 0..872);
+        assert_eq!(Span::parse("1_024..").unwrap().resolve(1000), 1000..1000);
+    }
+
+    #[test]
+    fn default_target_leaves_out_the_start() {
+        let regions = target(&[]).regions(1000, 1);
+
+        assert!(target(&[]).is_default());
+        assert_eq!(regions.regions, vec![20..1000]);
+    }
+
+    #[test]
+    fn overlapping_ranges_are_merged() {
+        let regions = target(&[("range", vec!["100..300", "200..400", "600..700"].into())]).regions(1000, 1);
+
+        assert_eq!(regions.regions, vec![100..400, 600..700]);
+        assert_eq!(regions.total(), 400);
+        assert_eq!(regions.largest(), 300);
+    }
+
+    #[test]
+    fn exclude_splits_a_region() {
+        let regions = target(&[("range", "100..900".into()), ("exclude", "400..500".into())]).regions(1000, 1);
+
+        assert_eq!(regions.regions, vec![100..400, 500..900]);
+        assert!(!regions.contains(450));
+        assert_eq!(regions.chunk(390, 50), 390..400);
+    }
+
+    #[test]
+    fn regions_are_aligned() {
+        let regions = target(&[("range", "10..95".into())]).regions(100, 4);
+
+        assert_eq!(regions.regions, vec![12..92]);
+    }
+
+    #[test]
+    fn picks_never_land_in_excluded_spans() {
+        let regions = target(&[("exclude", vec!["..250", "300..700", "-50.."].into())]).regions(1000, 1);
+        let mut rng = MutRng::seed_from_u64(1);
+
+        for _ in 0..10_000 {
+            let index = regions.pick(&mut rng, 1);
+            assert!((250..300).contains(&index) || (700..950).contains(&index), "{} was picked", index);
+        }
+    }
+
+    #[test]
+    fn drift_wraps_around_the_region() {
+        let regions = Regions { regions: vec![Range { start: 100, end: 200 }], explicit: false, shift: 130 };
+
+        assert_eq!(regions.drift(150, 100..200), 180);
+        assert_eq!(regions.drift(180, 100..200), 110);
+        assert_eq!(Regions { shift: 0, ..regions.clone() }.drift(180, 100..200), 180);
+        assert_eq!(Regions { shift: -30, ..regions }.drift(110, 100..200), 180);
+    }
+
+    #[test]
+    fn require_room_blames_the_range_only_if_set() {
+        let explicit = target(&[("range", "..100".into())]).regions(1000, 1);
+        assert!(explicit.require_room(1000, 99, 1, "Swap").is_ok());
+        assert!(matches!(explicit.require_room(1000, 100, 1, "Swap"),
+            Err(Error::InvalidOption { ref option, .. }) if option == "range"));
+
+        let default = target(&[]).regions(10, 1);
+        assert!(matches!(default.require_room(10, 10, 1, "Swap"), Err(Error::DataTooSmall { len: 10, .. })));
+    }
+}
@@ -0,0 +1,522 @@
+//! Checks the whole configuration before any work starts, so every problem can be reported at once.
+
+use super::batch::{self, Keyframes};
+use super::configuration::Configuration;
+use super::distribution::Distribution;
+use super::error::Error;
+use super::formats;
+use super::loaders::STREAM;
+use super::naming;
+use super::targeting::Target;
+use super::mutations::{OptionKind, OptionSpec};
+use super::registry;
+
+use std::collections::HashSet;
+use std::fs;
+
+use toml::Value;
+
+/// A problem found, along with the option it comes from.
+type Problem = (Option<String>, Error);
+
+/// Checks the global options, and the options of every mutation against what it declares.
+///
+/// Mutations that are used must have all of their required options. Sections of mutations that
+/// aren't used are still checked, but only for the options they do specify.
+pub fn validate(cfg: &Configuration) -> Result<(), Error> {
+    let mut problems: Vec<Problem> = Vec::new();
+
+    let input_len = check_globals(cfg, &mut problems);
+
+    let lists = match cfg.get_pool() {
+        // Chains are drawn in any order, so each mutation of the pool is checked as a list of its own.
+        Ok(Some(pool)) => {
+            if let Err(err) = cfg.get_chain_length() {
+                problems.push((Some("chain_length".into()), err));
+            }
+            pool.into_iter().filter(|(_, weight)| *weight > 0).map(|(name, _)| vec![name]).collect()
+        },
+        Ok(None) => match cfg.get_mutations() {
+            Ok(lists) => lists,
+            Err(err) => {
+                problems.push((Some("mutations".into()), err));
+                Vec::new()
+            },
+        },
+        Err(err) => {
+            problems.push((Some("pool".into()), err));
+            Vec::new()
+        },
+    };
+    let source = if cfg.raw().get("pool").is_some() { "pool" } else { "mutations" };
+
+    check_stream(cfg, source == "mutations" && lists.len() > 1, &mut problems);
+
+    // Only raw data is as large as the file itself.
+    let input_len = input_len.filter(|_| cfg.get_format().ok() == Some("raw"));
+
+    // Any mutation of a pool can come after one that resizes the data.
+    let pool_resizes = source == "pool" && lists.iter().flatten()
+        .any(|name| registry::get(name).is_some_and(|mutation| mutation.resizes()));
+
+    let mut used = HashSet::new();
+    for list in lists {
+        // Mutations after one that resizes the data may work on more than the file.
+        let mut resized = pool_resizes;
+
+        for name in list {
+            let mutation = match registry::get(name) {
+                Some(mutation) => mutation,
+                None => {
+                    problems.push((Some(source.into()), Error::Config(
+                        format!("Invalid mutation: {:?}\n\tOnly allowed mutations: {:?}", name, registry::names()))));
+                    continue;
+                },
+            };
+
+            if used.insert(name.clone()) {
+                check_options(cfg, name, &mutation.options(), true, &mut problems);
+            }
+            if !resized {
+                check_room(cfg, name, &mutation.options(), input_len, &mut problems);
+            }
+
+            resized |= mutation.resizes();
+        }
+    }
+
+    check_sections(cfg, &used, &mut problems);
+
+    into_error(cfg, problems)
+}
+
+/// Checks the global options that aren't read by mutations, returning the size of the input file.
+fn check_globals(cfg: &Configuration, problems: &mut Vec<Problem>) -> Option<u64> {
+    let mut input_len = None;
+
+    match batch::inputs(cfg) {
+        // Files of a batch too small for a mutation only fail on their own, and show up in its summary.
+        Ok(Some(_)) => (),
+        Ok(None) => match cfg.get_inputfile() {
+            // Stdin can only be read once, so its size is only known when bending.
+            Ok(STREAM) => (),
+            Ok(input) => match fs::metadata(input) {
+                Ok(metadata) => input_len = Some(metadata.len()),
+                Err(err) => problems.push((Some("inputfile".into()), Error::io(input, err))),
+            },
+            Err(err) => problems.push((Some("inputfile".into()), err)),
+        },
+        Err(err) => problems.push((Some("inputs".into()), err)),
+    }
+
+    for key in &["recursive", "sequence"] {
+        if cfg.raw().get(key).is_some_and(|value| !value.is_bool()) {
+            problems.push((Some(key.to_string()), Error::Config(format!("Must specify '{}' as a boolean.", key))));
+        }
+    }
+
+    // Only the files of a batch can be frames of a sequence.
+    if cfg.raw().get("sequence").and_then(Value::as_bool) == Some(true) && cfg.raw().get("inputs").is_none() {
+        problems.push((Some("sequence".into()), Error::Config("Must specify 'inputs' to bend a sequence.".into())));
+    }
+
+    if let Some(frame) = cfg.raw().get("frame") {
+        if !matches!(frame.as_integer(), Some(frame) if frame >= 0) {
+            problems.push((Some("frame".into()), Error::Config("Must specify 'frame' as a non-negative integer.".into())));
+        }
+    }
+
+    if let Err(err) = cfg.get_seed() {
+        problems.push((Some("seed".into()), err));
+    }
+
+    match cfg.get_format() {
+        Ok(format) => {
+            let known = ["raw", "auto"].contains(&format) || formats::formats().iter().any(|known| known.name() == format);
+            if !known {
+                problems.push((Some("format".into()), Error::Config(format!("Unknown format '{}'.", format))));
+            }
+        },
+        Err(err) => problems.push((Some("format".into()), err)),
+    }
+
+    let global = |key: &str| cfg.raw().get(key);
+
+    if let Some(times) = global("times") {
+        if !matches!(times.as_integer(), Some(times) if times >= 1) {
+            problems.push((Some("times".into()), Error::Config("Must specify 'times' as a positive integer.".into())));
+        }
+    }
+
+    if let Some(template) = global("output_template") {
+        let result = template.as_str()
+            .ok_or_else(|| "Must specify 'output_template' as a string.".to_string())
+            .and_then(naming::check);
+        if let Err(message) = result {
+            problems.push((Some("output_template".into()), Error::Config(message)));
+        }
+    }
+
+    if let Some(collision) = global("on_collision") {
+        if collision.as_str().and_then(naming::Collision::parse).is_none() {
+            problems.push((Some("on_collision".into()), Error::Config(
+                "Must specify 'on_collision' as either \"skip\", \"overwrite\" or \"suffix\".".into())));
+        }
+    }
+
+    if let Some(backend) = global("backend") {
+        if !matches!(backend.as_str(), Some("mmap") | Some("memory")) {
+            problems.push((Some("backend".into()), Error::Config("Must specify 'backend' as either \"mmap\" or \"memory\".".into())));
+        }
+    }
+
+    for &(key, kind) in &[("outputfile", "a string"), ("outputdir", "a string"), ("run_manifest", "a string"), ("manifest", "a boolean")] {
+        let valid = match global(key) {
+            Some(Value::String(_)) => key != "manifest",
+            Some(Value::Boolean(_)) => key == "manifest",
+            Some(_) => false,
+            None => true,
+        };
+        if !valid {
+            problems.push((Some(key.into()), Error::Config(format!("Must specify '{}' as {}.", key, kind))));
+        }
+    }
+
+    input_len
+}
+
+/// Checks that writing to stdout only ever writes a single output.
+fn check_stream(cfg: &Configuration, several_lists: bool, problems: &mut Vec<Problem>) {
+    let raw = cfg.raw();
+
+    let output = raw.get("outputfile").or_else(|| raw.get("inputfile")).and_then(Value::as_str);
+    if output != Some(STREAM) || raw.get("inputs").is_some() {
+        return;
+    }
+
+    let mut problem = |path: &str, message: &str| problems.push((Some(path.into()), Error::Config(message.into())));
+
+    if several_lists {
+        problem("mutations", "Can only write a single list of mutations to stdout.");
+    }
+    if raw.get("times").and_then(Value::as_integer).is_some_and(|times| times > 1) {
+        problem("times", "Can only write to stdout once, so 'times' must be 1.");
+    }
+    if raw.get("manifest").and_then(Value::as_bool) == Some(true) {
+        problem("manifest", "Can't write a manifest next to stdout. Its mutations are printed instead.");
+    }
+}
+
+/// Where an option is specified: under the section, or globally.
+fn find<'a>(cfg: &'a Configuration, section: &str, option: &str) -> Option<(String, &'a Value)> {
+    let raw = cfg.raw();
+
+    match raw.get(section).and_then(|table| table.get(option)) {
+        Some(value) => Some((format!("{}.{}", section, option), value)),
+        None => raw.get(option).map(|value| (option.to_string(), value)),
+    }
+}
+
+/// Checks every option a mutation declares. Missing options are only reported if `required` is set.
+fn check_options(cfg: &Configuration, name: &str, options: &[OptionSpec], required: bool, problems: &mut Vec<Problem>) {
+    let section = format!("{}Config", name);
+    let sequence = cfg.raw().get("sequence").and_then(Value::as_bool).unwrap_or(false);
+
+    for option in options {
+        match find(cfg, &section, option.name) {
+            Some((path, value)) => if let Err(expected) = check_value(value, option, sequence) {
+                problems.push((Some(path), Error::invalid_option(&section, option.name, expected)));
+            },
+            None => if required && option.required && option.default.is_none() {
+                let path = format!("{}.{}", section, option.name);
+                problems.push((Some(path), Error::invalid_option(&section, option.name, expected(option.kind))));
+            },
+        }
+    }
+
+    // Any mutation can be limited to parts of the data.
+    if let Err(error) = Target::from_config(cfg, &section) {
+        let path = match &error {
+            Error::InvalidOption { option, .. } => find(cfg, &section, option).map(|(path, _)| path),
+            _ => None,
+        };
+        problems.push((path, error));
+    }
+}
+
+/// What an option of `kind` is expected to be, as worded when it's missing.
+fn expected(kind: OptionKind) -> &'static str {
+    match kind {
+        OptionKind::Int => "a valid integer, or range",
+        OptionKind::Float => "a valid float, or range",
+        OptionKind::Text => "a string",
+    }
+}
+
+/// The numbers in an option, being either a single value or a `[min, max]` range.
+fn numbers(value: &Value, kind: OptionKind) -> Option<Vec<f64>> {
+    let number = |value: &Value| match (value, kind) {
+        (Value::Integer(int), _) => Some(*int as f64),
+        (Value::Float(float), OptionKind::Float) => Some(*float),
+        _ => None,
+    };
+
+    match value {
+        Value::Array(list) if !list.is_empty() && list.len() <= 2 => list.iter().map(number).collect(),
+        Value::Array(_) => None,
+        value => number(value).map(|number| vec![number]),
+    }
+}
+
+/// Checks the value of an option, returning what it was expected to be if it's invalid.
+///
+/// Keyframes are only valid in a `sequence`.
+fn check_value(value: &Value, option: &OptionSpec, sequence: bool) -> Result<(), String> {
+    if let OptionKind::Text = option.kind {
+        return value.as_str().map(|_| ()).ok_or_else(|| expected(option.kind).into());
+    }
+
+    if let Some(keyframes) = Keyframes::parse(value) {
+        let keyframes = keyframes?;
+
+        return match option.min {
+            _ if !sequence => Err("a single value, range or distribution, as keyframes only apply to a sequence".into()),
+            _ if option.kind == OptionKind::Int && !keyframes.is_integer() => Err("keyframes set to integers".into()),
+            Some(min) if keyframes.values().any(|value| value < min) => Err(format!("keyframes of at least {}", min)),
+            _ => Ok(()),
+        };
+    }
+
+    if value.is_table() {
+        let lowest = Distribution::parse(&value.clone().into())?.lowest();
+
+        return match option.min {
+            Some(min) if lowest.is_none_or(|lowest| lowest < min) =>
+                Err(format!("a distribution that never draws below {}", min)),
+            _ => Ok(()),
+        };
+    }
+
+    let numbers = numbers(value, option.kind).ok_or_else(|| expected(option.kind).to_string())?;
+
+    if let [min, max] = numbers.as_slice() {
+        if min > max {
+            return Err(format!("a range from its lowest to its highest value, not {}", value));
+        }
+    }
+
+    match option.min {
+        Some(min) if numbers[0] < min => Err(format!("a value of at least {}, not {}", min, value)),
+        _ => Ok(()),
+    }
+}
+
+/// The lowest value an option can take, if it's valid.
+fn lowest(value: &Value, kind: OptionKind) -> Option<f64> {
+    if let Some(keyframes) = Keyframes::parse(value) {
+        return keyframes.ok()?.values().reduce(f64::min);
+    }
+
+    match value {
+        Value::Table(_) => Distribution::parse(&value.clone().into()).ok()?.lowest(),
+        value => numbers(value, kind).map(|numbers| numbers.into_iter().fold(f64::MAX, f64::min)),
+    }
+}
+
+/// Checks that the smallest `chunksize` a mutation could pick fits in the input file.
+fn check_room(cfg: &Configuration, name: &str, options: &[OptionSpec], len: Option<u64>, problems: &mut Vec<Problem>) {
+    let section = format!("{}Config", name);
+
+    // Only the default target is known to start at 2% of the data.
+    if !Target::from_config(cfg, &section).is_ok_and(|target| target.is_default()) {
+        return;
+    }
+
+    let chunksize = options.iter()
+        .find(|option| option.name == "chunksize")
+        .and_then(|option| find(cfg, &section, option.name).map(|found| (found, option.kind)))
+        .and_then(|((path, value), kind)| Some((path, lowest(value, kind)? as u64)));
+
+    if let (Some((path, chunksize)), Some(len)) = (chunksize, len) {
+        // Indices are never picked from the first 2% of the data.
+        let needed = chunksize + chunksize / 49 + 1;
+
+        if len < needed {
+            let error = Error::DataTooSmall { mutation: name.into(), len: len as usize, needed: needed as usize };
+            problems.push((Some(path), error));
+        }
+    }
+}
+
+/// Checks the sections of mutations that aren't used, and that every section belongs to something.
+fn check_sections(cfg: &Configuration, used: &HashSet<String>, problems: &mut Vec<Problem>) {
+    let sections = match cfg.raw().as_table() {
+        Some(table) => table.iter().filter(|(_, value)| value.is_table()).map(|(key, _)| key),
+        None => return,
+    };
+
+    let format_sections: Vec<String> = formats::formats().iter()
+        .map(|format| {
+            let mut name = format.name().to_string();
+            name[..1].make_ascii_uppercase();
+            format!("{}Config", name)
+        })
+        .collect();
+
+    // Global options can be tables too, such as the pool or distributions.
+    let options: HashSet<&str> = registry::names().iter()
+        .filter_map(|name| registry::get(name))
+        .flat_map(|mutation| mutation.options().into_iter().map(|option| option.name))
+        .chain(std::iter::once("pool"))
+        .collect();
+
+    for section in sections.filter(|section| !options.contains(section.as_str())) {
+        let mutation = section.strip_suffix("Config")
+            .and_then(|name| registry::get(name).map(|mutation| (name, mutation)));
+
+        match mutation {
+            Some((name, mutation)) => if !used.contains(name) {
+                check_options(cfg, name, &mutation.options(), false, problems);
+            },
+            None => if !format_sections.contains(section) {
+                problems.push((Some(section.clone()), Error::Config(
+                    format!("Unknown section '[{}]': it matches neither a mutation nor a format.", section))));
+            },
+        }
+    }
+}
+
+/// Reports every problem at once, pointing at their lines in the options file when known.
+fn into_error(cfg: &Configuration, problems: Vec<Problem>) -> Result<(), Error> {
+    // An invalid global option can be read by several mutations, but it's only one problem.
+    let mut seen = HashSet::new();
+    let problems = problems.into_iter().filter(|(path, error)| match error {
+        Error::InvalidOption { .. } => seen.insert(format!("{:?}", path)),
+        _ => seen.insert(format!("{:?} {}", path, error)),
+    });
+
+    // Missing options point at their section instead.
+    let locate = |path: &str| cfg.locate(path)
+        .or_else(|| cfg.locate(path.split('.').next().unwrap_or_default()));
+
+    let mut errors: Vec<(Option<usize>, Error)> = problems
+        .map(|(path, error)| match path.as_deref().and_then(locate) {
+            Some((file, line)) => (Some(line), error.at(file, line)),
+            None => (None, error),
+        })
+        .collect();
+
+    // In the order of the options file, followed by options given on the command line.
+    errors.sort_by_key(|(line, _)| line.unwrap_or(usize::MAX));
+
+    let mut errors: Vec<Error> = errors.into_iter().map(|(_, error)| error).collect();
+
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Several(errors)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Validates an options file, with `{input}` replaced by an input file of `len` bytes.
+    fn validate_file(test: &str, options: &str, len: usize) -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("glitchup-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.bin");
+        fs::write(&input, vec![0; len]).unwrap();
+        let path = dir.join("options.toml");
+        fs::write(&path, options.replace("{input}", &input.to_string_lossy().replace('\\', "/"))).unwrap();
+
+        let result = Configuration::from_file(&path.to_string_lossy()).and_then(|cfg| validate(&cfg));
+
+        fs::remove_dir_all(&dir).unwrap();
+        result
+    }
+
+    fn lines(error: &Error) -> Vec<Option<usize>> {
+        let line = |error: &Error| match error {
+            Error::At { line, .. } => Some(*line),
+            _ => None,
+        };
+
+        match error {
+            Error::Several(errors) => errors.iter().map(line).collect(),
+            error => vec![line(error)],
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_its_line() {
+        let options = "inputfile = '{input}'\nmutations = [['Loops']]\n\n[LoopsConfig]\niterations = 1\nloops = 'many'\nchunksize = 0\n\n[Typo]\nchunksize = 1\n";
+        let error = validate_file("validate-lines", options, 1000).unwrap_err();
+
+        assert_eq!(lines(&error), vec![Some(6), Some(7), Some(9)]);
+        let message = error.to_string();
+        assert!(message.contains("'loops' to be under 'LoopsConfig'"), "{}", message);
+        assert!(message.contains("a value of at least 1, not 0"), "{}", message);
+        assert!(message.contains("Unknown section '[Typo]'"), "{}", message);
+        assert_eq!(error.exit_code(), 4);
+    }
+
+    #[test]
+    fn options_missing_from_the_file_point_at_their_section() {
+        let options = "inputfile = '{input}'\nmutations = [['Loops']]\n\n[LoopsConfig]\niterations = 1\nchunksize = 10\n";
+        let error = validate_file("validate-missing", options, 1000).unwrap_err();
+
+        assert_eq!(lines(&error), vec![Some(4)]);
+    }
+
+    #[test]
+    fn chunks_must_fit_in_the_input() {
+        let options = "inputfile = '{input}'\nmutations = [['Swap']]\niterations = 1\nchunksize = 100\n";
+
+        assert!(validate_file("validate-room", options, 103).is_ok());
+        let error = validate_file("validate-room", options, 102).unwrap_err();
+        assert!(matches!(&error, Error::At { line: 4, error, .. } if matches!(**error, Error::DataTooSmall { needed: 103, .. })), "{:?}", error);
+    }
+
+    #[test]
+    fn values_are_checked_against_their_bound() {
+        let option = OptionSpec::new("chunksize", "ch", OptionKind::Int).at_least(1.0);
+        let value = |text: &str| toml::from_str::<Value>(&format!("value = {}", text)).unwrap()["value"].clone();
+
+        for valid in &["1", "[1, 5]", "{ dist = 'uniform', min = 1, max = 4 }"] {
+            assert_eq!(check_value(&value(valid), &option, false), Ok(()), "{}", valid);
+        }
+        for invalid in &["0", "[0, 5]", "[5, 2]", "1.5", "'large'", "{ dist = 'uniform', min = 0, max = 4 }", "{ keyframes = [[0, 1]] }"] {
+            assert!(check_value(&value(invalid), &option, false).is_err(), "{}", invalid);
+        }
+
+        assert_eq!(check_value(&value("{ keyframes = [[0, 1], [5, 10]] }"), &option, true), Ok(()));
+        assert!(check_value(&value("{ keyframes = [[0, 0], [5, 10]] }"), &option, true).is_err());
+        assert!(check_value(&value("{ keyframes = [[0, 1.5]] }"), &option, true).is_err());
+    }
+
+    #[test]
+    fn declared_bounds_are_enforced() {
+        for name in registry::names() {
+            for option in registry::get(&name).unwrap().options() {
+                let number = |value: f64| match option.kind {
+                    OptionKind::Int => Value::Integer(value as i64),
+                    _ => Value::Float(value),
+                };
+
+                match (option.kind, option.min) {
+                    (OptionKind::Text, _) => {
+                        assert_eq!(check_value(&Value::String("text".into()), &option, false), Ok(()));
+                        assert!(check_value(&Value::Integer(1), &option, false).is_err(), "{}.{}", name, option.name);
+                    },
+                    (_, Some(min)) => {
+                        assert_eq!(check_value(&number(min), &option, false), Ok(()), "{}.{}", name, option.name);
+                        assert!(check_value(&number(min - 1.0), &option, false).is_err(), "{}.{}", name, option.name);
+                    },
+                    (_, None) => assert_eq!(check_value(&number(-1e6), &option, false), Ok(()), "{}.{}", name, option.name),
+                }
+            }
+        }
+    }
+}